@@ -1,2 +1,3 @@
 pub mod cli;
+pub mod logging;
 pub mod runtime;
\ No newline at end of file
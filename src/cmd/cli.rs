@@ -1,12 +1,12 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::{HashMap, BTreeMap}, path::PathBuf, io::Write};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, Context};
 use clap::Parser;
 use requestty::Question;
 use semver::Version;
 use tinytemplate::TinyTemplate;
 
-use crate::{core::manifest::Manifest, loading::{base::Loader, saves::SaveManager}};
+use crate::{core::{manifest::Manifest, resources::Resources, prompt::Prompt}, game::auto::{self, Strategy, Outcome, Coverage}, loading::{base::Loader, saves::SaveManager}};
 
 pub const TEMPLATE_MANIFEST: &'static str = include_str!("../template/nage.yml");
 pub const TEMPLATE_MAIN: &'static str = include_str!("../template/main.yml");
@@ -18,10 +18,22 @@ pub enum CliCommand {
 	Run {
 		#[arg(help = "The game directory. Defaults to the current directory")]
 		path: Option<PathBuf>,
-		#[arg(short, long, help = "Start a new save file")]
+		#[arg(short, long, conflicts_with_all = ["save", "choose"], help = "Start a new save file")]
 		new: bool,
-		#[arg(short, long, help = "Pick from a list of multiple saves instead of the last used")]
-		pick: bool
+		#[arg(short, long, conflicts_with = "choose", help = "The save file to load. Defaults to continuing from the last save")]
+		save: Option<String>,
+		#[arg(short, long, alias = "pick", short_alias = 'p', help = "Choose from the existing saves instead of continuing from the last save")]
+		choose: bool,
+		#[arg(long, help = "The profile to play as. Defaults to selecting from existing profiles, if any")]
+		profile: Option<String>,
+		#[arg(long, help = "Skip the first-run language and profile selection")]
+		no_setup: bool,
+		#[arg(long, help = "Disable audio, overriding the game's settings")]
+		no_audio: bool,
+		#[arg(long, help = "Print all text instantly and skip waits, overriding the game's settings")]
+		instant: bool,
+		#[arg(short, long, action = clap::ArgAction::Count, help = "Log more details to stderr; repeat for more")]
+		verbose: u8
 	},
 	#[command(about = "Create a new Nagame template")]
 	New {
@@ -29,7 +41,32 @@ pub enum CliCommand {
 		full: bool
 	},
 	#[command(about = "Open the save directory")]
-	Saves
+	Saves {
+		#[arg(long, help = "The profile whose saves to open. Defaults to the default profile")]
+		profile: Option<String>
+	},
+	#[command(about = "Play a Nagame automatically and report the endings reached")]
+	Autoplay {
+		#[arg(help = "The game directory. Defaults to the current directory")]
+		path: Option<PathBuf>,
+		#[arg(short, long, value_enum, default_value_t = Strategy::Random, help = "How choices are picked")]
+		strategy: Strategy,
+		#[arg(short, long, default_value_t = 1, help = "The amount of playthroughs")]
+		runs: usize,
+		#[arg(short, long, default_value_t = 1000, help = "The maximum amount of prompts visited per playthrough")]
+		max_steps: usize,
+		#[arg(short, long, help = "Report which prompts and choices were never visited")]
+		coverage: bool,
+		#[arg(short, long, help = "Print the text displayed during a single playthrough, without animation")]
+		transcript: bool
+	},
+	#[command(about = "Validate a Nagame's content and translation coverage")]
+	Validate {
+		#[arg(help = "The game directory. Defaults to the current directory")]
+		path: Option<PathBuf>,
+		#[arg(short, long, help = "Only report translation coverage for this language")]
+		lang: Option<String>
+	}
 }
 
 impl CliCommand {
@@ -74,10 +111,86 @@ impl CliCommand {
 	}
 
 	/// Handles a [`Saves`](CliCommand::Saves) command.
-	fn saves() -> Result<()> {
+	fn saves(profile: Option<&str>) -> Result<()> {
 		let loader = Loader::new(PathBuf::new());
 		let config = Manifest::load(&loader)?;
-		let _ = open::that(SaveManager::dir(&config, false)?)?;
+		open::that(SaveManager::dir(&config, profile, false)?)?;
+		Ok(())
+	}
+
+	/// Handles a [`Validate`](CliCommand::Validate) command.
+	/// 
+	/// Invalid prompts are an error, while unknown variables, unusable choices, and gaps in translation coverage are only reported.
+	fn validate(path: Option<&PathBuf>, lang: Option<&String>) -> Result<()> {
+		let loader = Loader::new(path.cloned().unwrap_or(PathBuf::new()));
+		let config = Manifest::load(&loader)?;
+		let resources = Resources::load(&loader, &config)?;
+		resources.validate_entry(&config)?;
+		resources.validate_assets(&config, true)?;
+		Prompt::validate_all(&resources.prompts, &resources.scripts, &config)?;
+		println!("Prompts are valid");
+		for (file, prompt, variable) in Prompt::unknown_variables(&resources.prompts, &config)? {
+			println!("Warning: prompt '{prompt}' in file '{file}' references unknown variable '{variable}'");
+		}
+		for (file, prompt, version) in Prompt::version_mismatches(&resources.prompts, &config)? {
+			println!("Warning: prompt '{prompt}' in file '{file}' requires game version '{version}', but the game is version '{}'", config.metadata.version);
+		}
+		for (file, prompt, choice, issue) in Prompt::lint_all(&resources.prompts, &config)? {
+			println!("Warning: choice #{choice} of prompt '{prompt}' in file '{file}' {issue}");
+		}
+		let coverage = resources.translation_coverage()?;
+		if let Some(lang) = lang {
+			if !coverage.contains_key(lang) {
+				return Err(anyhow!("Invalid language '{lang}'"));
+			}
+		}
+		for (name, file) in coverage.iter().filter(|(name, _)| lang.map(|lang| lang == **name).unwrap_or(true)) {
+			if file.is_complete() {
+				println!("Translations '{name}' are complete");
+				continue;
+			}
+			println!("Translations '{name}': {} missing, {} extra", file.missing.len(), file.extra.len());
+			for key in &file.missing {
+				println!("  missing: {key}");
+			}
+			for key in &file.extra {
+				println!("  extra: {key}");
+			}
+		}
+		Ok(())
+	}
+
+	/// Handles an [`Autoplay`](CliCommand::Autoplay) command.
+	/// 
+	/// A single playthrough reports its full path, while multiple report the distribution of their outcomes.
+	fn autoplay(path: Option<&PathBuf>, strategy: Strategy, runs: usize, max_steps: usize, report_coverage: bool, transcript: bool) -> Result<()> {
+		if transcript && runs != 1 {
+			return Err(anyhow!("A transcript can only be printed for a single playthrough"));
+		}
+		let loader = Loader::new(path.cloned().unwrap_or(PathBuf::new()));
+		let config = Manifest::load(&loader)?;
+		let resources = Resources::load(&loader, &config)?;
+		let mut outcomes: BTreeMap<Outcome, usize> = BTreeMap::new();
+		let mut coverage = Coverage::default();
+		for run in 1..=runs {
+			let mut stdout = std::io::stdout();
+			let out: Option<&mut dyn Write> = if transcript { Some(&mut stdout) } else { None };
+			let playthrough = auto::play(&config, &resources, strategy, max_steps, &mut coverage, out)
+				.with_context(|| format!("Error during playthrough #{run}"))?;
+			if runs == 1 {
+				println!("{}\n\n{}", playthrough.path.join(" -> "), playthrough.outcome);
+			}
+			*outcomes.entry(playthrough.outcome).or_insert(0) += 1;
+		}
+		if runs > 1 {
+			println!("Outcomes across {runs} playthroughs:");
+			for (outcome, count) in outcomes {
+				println!("- {outcome}: {count} ({:.1}%)", count as f64 / runs as f64 * 100.0);
+			}
+		}
+		if report_coverage {
+			println!("\n{}", coverage.report(&resources)?);
+		}
 		Ok(())
 	}
 
@@ -85,7 +198,9 @@ impl CliCommand {
 		use CliCommand::*;
 		match self {
 			&New { full } => Self::new(full),
-			Saves => Self::saves(),
+			Saves { profile } => Self::saves(profile.as_deref()),
+			&Autoplay { ref path, strategy, runs, max_steps, coverage, transcript } => Self::autoplay(path.as_ref(), strategy, runs, max_steps, coverage, transcript),
+			Validate { path, lang } => Self::validate(path.as_ref(), lang.as_ref()),
 			_ => unreachable!()
 		}
 	}
@@ -0,0 +1,52 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Writes log records to standard error so that gameplay on standard output stays clean.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		// Dependencies only report problems, since their details are too noisy to be useful
+		let max_level = if metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+			log::max_level()
+		}
+		else {
+			log::max_level().min(DEFAULT_LEVEL)
+		};
+		metadata.level() <= max_level
+	}
+
+	fn log(&self, record: &Record) {
+		if self.enabled(record.metadata()) {
+			eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// The level logged by default, so that only problems are reported.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Warn;
+
+/// Installs the logger with a level raised by the amount of `--verbose` flags given:
+/// once for [`Info`](Level::Info), twice for [`Debug`](Level::Debug), and more for [`Trace`](Level::Trace).
+pub fn init(verbosity: u8) {
+	let level = match verbosity {
+		0 => DEFAULT_LEVEL,
+		1 => LevelFilter::Info,
+		2 => LevelFilter::Debug,
+		_ => LevelFilter::Trace
+	};
+	// Only fails if a logger is already installed, in which case that one is kept
+	if log::set_logger(&LOGGER).is_ok() {
+		log::set_max_level(level);
+	}
+}
+
+/// Raises the log level to at least [`Debug`](Level::Debug), such as when the game's `debug` setting is enabled.
+pub fn enable_debug() {
+	if log::max_level() < Level::Debug {
+		log::set_max_level(LevelFilter::Debug);
+	}
+}
@@ -1,31 +1,106 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Mutex};
+
 use anyhow::{Result, anyhow};
-use clap::Parser;
+use clap::{Parser, Subcommand, CommandFactory};
+
+use crate::{core::{choice::note_namespace, player::Player, prompt::Prompt as PromptUtil, manifest::Manifest, resources::{UnlockedInfoPages, InfoPages, Resources}, audio::Audio}, game::{gloop::GameLoopResult}, loading::saves::{SaveManager, SaveBundle}, text::{context::TextContext, display::{Translations, PlayerTextSpeed}}};
+use strum::IntoEnumIterator;
 
-use crate::{core::{player::Player, prompt::Prompt as PromptUtil, manifest::Manifest, choice::Notes, resources::{UnlockedInfoPages, InfoPages, Resources}, audio::Audio}, game::{gloop::GameLoopResult}, loading::saves::SaveManager, text::{context::TextContext, display::Translations}};
+/// The clipboard used by the [`Copy`](RuntimeCommand::Copy) command, created upon first use.
+static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
 
 #[derive(Parser, Debug, PartialEq)]
-#[command(multicall = true)]
+#[command(multicall = true, disable_help_subcommand = true)]
 pub enum RuntimeCommand {
+	#[command(about = "List the available commands")]
+	Help,
 	#[command(about = "Try going back a choice")]
 	Back,
 	#[command(about = "Manage the display language")]
-	Lang,
+	Lang {
+		#[arg(help = "The language to switch to. If omitted, select from the available languages")]
+		lang: Option<String>
+	},
 	#[command(about = "Display an info page")]
 	Info,
 	#[command(about = "Display an action log page")]
 	Log,
+	#[command(about = "Display every action log entry gained so far")]
+	Journal,
 	#[command(about = "Manage sound effects and music channels")]
 	Sound,
+	#[command(about = "View and adjust player settings")]
+	Settings,
+	#[command(about = "Pause sound channels")]
+	Pause {
+		#[arg(help = "The channel to pause. Defaults to all channels")]
+		channel: Option<String>
+	},
+	#[command(about = "Resume paused sound channels")]
+	Resume {
+		#[arg(help = "The channel to resume. Defaults to all channels")]
+		channel: Option<String>
+	},
+	#[command(about = "Display the current prompt's location")]
+	Where,
+	#[command(about = "Display the description of a choice")]
+	About {
+		#[arg(help = "The label of the choice, as displayed in the list")]
+		choice: String
+	},
 	#[command(about = "Save the player data")]
 	Save,
+	#[command(about = "Export the player data to a portable save bundle")]
+	ExportSave {
+		#[arg(help = "The file to write the save bundle to")]
+		file: PathBuf
+	},
+	#[command(about = "Replace the player data with an exported save bundle")]
+	ImportSave {
+		#[arg(help = "The save bundle file to read")]
+		file: PathBuf
+	},
 	#[command(about = "Save and quits the game")]
 	Quit,
 	#[command(about = "Display debug info about a prompt", hide = true)]
 	Prompt,
+	#[command(about = "Display the current prompt's model, choices, notes, and variables", hide = true)]
+	Debug,
+	#[command(about = "Display the prompts reachable from the current prompt", hide = true)]
+	Map {
+		#[arg(default_value_t = 2, help = "The maximum amount of choices away from the current prompt")]
+		depth: usize
+	},
 	#[command(about = "List the currently applied notes", hide = true)]
 	Notes,
 	#[command(about = "List the currently applied variable names and their values", hide = true)]
 	Variables,
+	#[command(about = "Copy the current player data to the clipboard", hide = true)]
+	Copy,
+	#[command(about = "Force a note, info page, or variable onto the player", hide = true)]
+	Unlock {
+		#[command(subcommand)]
+		target: UnlockTarget
+	}
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+/// The player state forced by an [`Unlock`](RuntimeCommand::Unlock) command.
+pub enum UnlockTarget {
+	#[command(about = "Apply a note")]
+	Note {
+		name: String
+	},
+	#[command(about = "Unlock an info page")]
+	Info {
+		page: String
+	},
+	#[command(about = "Set a variable")]
+	Variable {
+		name: String,
+		#[arg(required = true, help = "The value to set, which may contain spaces")]
+		value: Vec<String>
+	}
 }
 
 /// The result of a runtime command.
@@ -47,11 +122,27 @@ impl RuntimeCommand {
 	fn is_normal(&self) -> bool {
 		use RuntimeCommand::*;
 		match self {
-			Back | Lang | Info | Log | Sound | Save | Quit => true,
+			Help | Back | Lang { .. } | Info | Log | Journal | Sound | Settings | Pause { .. } | Resume { .. } | Where | About { .. } | Save | ExportSave { .. } | ImportSave { .. } | Quit => true,
 			_ => false
 		}
 	}
 
+	/// Handles a [`Help`](RuntimeCommand::Help) command.
+	/// 
+	/// The list is generated from the command definitions. Debug commands are only listed in debug mode.
+	fn help(debug: bool) -> Result<CommandResult> {
+		let command = Self::command();
+		let commands: Vec<String> = command.get_subcommands()
+			.filter(|subcommand| debug || !subcommand.is_hide_set())
+			.map(|subcommand| {
+				let about = subcommand.get_about().map(|about| about.to_string()).unwrap_or_default();
+				let debug_only = if subcommand.is_hide_set() { " (debug)" } else { "" };
+				format!(".{:<10} {about}{debug_only}", subcommand.get_name())
+			})
+			.collect();
+		Ok(CommandResult::Output(format!("\n{}", commands.join("\n"))))
+	}
+
 	/// Handles a [`Back`](RuntimeCommand::Back) command.
 	fn back(player: &mut Player) -> Result<CommandResult> {
 		if player.history.len() <= 1 {
@@ -62,20 +153,36 @@ impl RuntimeCommand {
 	}
 
 	/// Handles a [`Lang`](RuntimeCommand::Lang) command.
-	fn lang(player: &mut Player, translations: &Translations) -> Result<CommandResult> {
+	/// 
+	/// The language is stored in the player data, and the current prompt is displayed again in the new language.
+	fn lang(player: &mut Player, lang: &Option<String>, translations: &Translations) -> Result<CommandResult> {
 		if translations.is_empty() {
 			return Err(anyhow!("No display languages loaded"));
 		}
 
-		println!();
-
-		let lang_question = requestty::Question::select("Select a language")
-			.choices(translations.keys())
-			.build();
-		let lang_choice = requestty::prompt_one(lang_question)?;
-		player.lang = lang_choice.as_list_item().unwrap().text.clone();
+		let selected = match lang {
+			Some(lang) => {
+				if !translations.contains_key(lang) {
+					let available = itertools::join(translations.keys(), ", ");
+					return Err(anyhow!("Invalid language '{lang}'; available languages: {available}"));
+				}
+				lang.clone()
+			},
+			None => {
+				println!();
+				let lang_question = requestty::Question::select("Select a language")
+					.choices(translations.keys())
+					.build();
+				let lang_choice = requestty::prompt_one(lang_question)?;
+				lang_choice.as_list_item().unwrap().text.clone()
+			}
+		};
 
-		Ok(CommandResult::retry())
+		if selected == player.lang {
+			return Ok(CommandResult::retry());
+		}
+		player.lang = selected;
+		Ok(CommandResult::Submit(GameLoopResult::Continue))
 	}
 
 	/// Handles an [`Info`](RuntimeCommand::Info) command.
@@ -116,6 +223,20 @@ impl RuntimeCommand {
 		Ok(CommandResult::Output(format!("\n{entries}")))
 	}
 
+	/// Handles a [`Journal`](RuntimeCommand::Journal) command.
+	/// 
+	/// Unlike [`Log`](RuntimeCommand::Log), displays every entry of the player's journal at once, rendered as Markdown.
+	fn journal(player: &Player) -> Result<CommandResult> {
+		if player.journal.is_empty() {
+			return Err(anyhow!("No journal entries to display"))
+		}
+
+		println!();
+		termimad::print_text(&player.journal_text());
+
+		Ok(CommandResult::retry())
+	}
+
 	/// Handles a [`Sound`](RuntimeCommand::Sound) command.
 	fn sound(player: &mut Player, audio_res: &Option<Audio>) -> Result<CommandResult> {
 		let audio = audio_res.as_ref()
@@ -150,8 +271,103 @@ impl RuntimeCommand {
 		Ok(CommandResult::retry())
 	}
 
+	/// Handles a [`Settings`](RuntimeCommand::Settings) command.
+	/// 
+	/// Settings decided by the game are displayed first and can't be changed. The player's own settings
+	/// are selected from a menu until the player is done; changing the language displays the current prompt again.
+	fn settings(player: &mut Player, config: &Manifest, resources: &Resources) -> Result<CommandResult> {
+		let on_off = |value: bool| if value { "on" } else { "off" };
+		let history = if config.settings.history.unbounded { "unbounded".to_owned() } else { config.settings.history.size.to_string() };
+		println!();
+		println!("Saving: {}", on_off(config.settings.save));
+		println!("Debug mode: {}", on_off(config.settings.debug));
+		println!("History size: {history}");
+		println!();
+
+		let lang = player.lang.clone();
+		loop {
+			let settings_question = requestty::Question::select("Settings")
+				.choices(vec![
+					format!("Text speed: {}", player.speed),
+					format!("Language: {}", player.lang),
+					"Sound channels".to_owned(),
+					"Done".to_owned()
+				])
+				.build();
+			let settings_choice = requestty::prompt_one(settings_question)?;
+
+			let result = match settings_choice.as_list_item().unwrap().index {
+				0 => {
+					let speeds: Vec<PlayerTextSpeed> = PlayerTextSpeed::iter().collect();
+					let speed_question = requestty::Question::select("Select a text speed")
+						.choices(speeds.iter().map(ToString::to_string))
+						.default(speeds.iter().position(|speed| speed == &player.speed).unwrap_or_default())
+						.build();
+					let speed_choice = requestty::prompt_one(speed_question)?;
+					player.speed = speeds[speed_choice.as_list_item().unwrap().index];
+					Ok(CommandResult::retry())
+				},
+				1 => Self::lang(player, &None, &resources.translations),
+				2 => Self::sound(player, &resources.audio),
+				_ => break
+			};
+			if let Err(err) = result {
+				println!("Error: {err}");
+			}
+		}
+
+		if player.lang != lang {
+			return Ok(CommandResult::Submit(GameLoopResult::Continue));
+		}
+		Ok(CommandResult::retry())
+	}
+
+	/// Handles a [`Pause`](RuntimeCommand::Pause) or [`Resume`](RuntimeCommand::Resume) command.
+	fn pause(audio_res: &Option<Audio>, channel: &Option<String>, resume: bool) -> Result<CommandResult> {
+		let audio = audio_res.as_ref()
+			.ok_or(anyhow!("No sound channels loaded"))?;
+		let channels: Vec<&String> = match channel {
+			Some(channel) => vec![channel],
+			None => audio.players.keys().collect()
+		};
+		for channel in channels {
+			if resume {
+				audio.resume(channel)?;
+			}
+			else {
+				audio.pause(channel)?;
+			}
+		}
+		Ok(CommandResult::retry())
+	}
+
+	/// Handles a [`Where`](RuntimeCommand::Where) command.
+	/// 
+	/// Outputs the current prompt's `file:prompt` coordinates, followed by its model in debug mode.
+	fn where_(player: &Player, resources: &Resources, text_context: &TextContext, debug: bool) -> Result<CommandResult> {
+		let path = &player.latest_entry()?.path;
+		let location = format!("{}:{}", path.file, path.prompt);
+		if !debug {
+			return Ok(CommandResult::Output(location));
+		}
+		let prompt = PromptUtil::get_from_path(&resources.prompts, path)?;
+		Ok(CommandResult::Output(format!("{location}\n{}", prompt.model(text_context)?)))
+	}
+
+	/// Handles an [`About`](RuntimeCommand::About) command.
+	/// 
+	/// The choice is selected by its label among the current prompt's usable choices, the same way as when taking it.
+	fn about(player: &Player, resources: &Resources, text_context: &TextContext, label: &str) -> Result<CommandResult> {
+		let prompt = PromptUtil::get_from_path(&resources.prompts, &player.latest_entry()?.path)?;
+		let choices = prompt.usable_choices(text_context)?;
+		let index = text_context.config.settings.choice_labels.parse(label, choices.len())?;
+		let description = choices[index - 1].description.as_ref()
+			.ok_or(anyhow!("Choice '{label}' has no description"))?;
+		Ok(CommandResult::Output(description.fill(text_context)?))
+	}
+
 	/// Handles a [`Prompt`](RuntimeCommand::Prompt) command.
-	fn prompt(notes: &Notes, resources: &Resources, text_context: &TextContext) -> Result<CommandResult> {
+	fn prompt(resources: &Resources, text_context: &TextContext) -> Result<CommandResult> {
 		println!();
 
 		let file_question = requestty::Question::select("Prompt file")
@@ -167,15 +383,74 @@ impl RuntimeCommand {
 		let prompt_name = &prompt_choice.as_list_item().unwrap().text;
 
 		let prompt = PromptUtil::get(&resources.prompts, prompt_name, file)?;
-		Ok(CommandResult::Output(prompt.debug_info(prompt_name, file, &resources.prompts, notes, text_context)?))
+		Ok(CommandResult::Output(prompt.debug_info(prompt_name, file, &resources.prompts, text_context)?))
+	}
+
+	/// Handles a [`Debug`](RuntimeCommand::Debug) command.
+	/// 
+	/// Lists every choice of the current prompt along with whether it is usable and, if not, why.
+	fn debug(player: &Player, resources: &Resources, text_context: &TextContext) -> Result<CommandResult> {
+		let path = &player.latest_entry()?.path;
+		let prompt = PromptUtil::get_from_path(&resources.prompts, path)?;
+		let model = prompt.model(text_context)?;
+		let usable = prompt.usable_choices(text_context)?.len();
+
+		let choices = prompt.choices.iter().enumerate()
+			.map(|(index, choice)| {
+				let response = choice.response.as_ref()
+					.map(|text| text.get(text_context))
+					.unwrap_or(Ok("(no response)".to_owned()))?;
+				let status = choice.gating_reason(text_context)?
+					.map(|reason| format!("hidden; {reason}"))
+					.unwrap_or("usable".to_owned());
+				Ok(format!("#{} {response} ({status})", index + 1))
+			})
+			.collect::<Result<Vec<String>>>()?
+			.join("\n");
+		let notes = if player.notes.is_empty() { "None".to_owned() } else { itertools::join(&player.notes, ", ") };
+		let variables: Vec<String> = player.variables.iter()
+			.map(|(name, value)| format!("{name}: {value}"))
+			.collect();
+		let variables = if variables.is_empty() { "None".to_owned() } else { variables.join("\n") };
+
+		let result = format!(
+			"\nID: {}/{}\n{model}\n\nChoices ({usable} of {} usable):\n{choices}\n\nNotes: {notes}\n\nVariables:\n{variables}",
+			path.file, path.prompt, prompt.choices.len()
+		);
+		Ok(CommandResult::Output(result))
+	}
+
+	/// The largest depth accepted by a [`Map`](RuntimeCommand::Map) command.
+	const MAX_MAP_DEPTH: usize = 5;
+
+	/// Handles a [`Map`](RuntimeCommand::Map) command.
+	fn map(player: &Player, resources: &Resources, depth: usize) -> Result<CommandResult> {
+		if depth > Self::MAX_MAP_DEPTH {
+			return Err(anyhow!("Map depth can't exceed {}", Self::MAX_MAP_DEPTH));
+		}
+		let map = PromptUtil::map(&resources.prompts, &player.latest_entry()?.path, depth)?;
+		Ok(CommandResult::Output(format!("\n{map}")))
 	}
 
 	/// Handles a [`Notes`](RuntimeCommand::Notes) command.
+	/// 
+	/// Namespaced notes are listed on separate lines grouped by their namespace, following any flat notes.
 	fn notes(player: &Player) -> Result<CommandResult> {
 		if player.notes.is_empty() {
 			return Err(anyhow!("No notes applied"))
 		}
-		let result = itertools::join(&player.notes, ", ");
+		let mut groups: BTreeMap<Option<&str>, Vec<&str>> = BTreeMap::new();
+		for note in &player.notes {
+			let (namespace, name) = note_namespace(note);
+			groups.entry(namespace).or_default().push(name);
+		}
+		let result = groups.into_iter()
+			.map(|(namespace, names)| match namespace {
+				Some(namespace) => format!("{namespace}: {}", names.join(", ")),
+				None => names.join(", ")
+			})
+			.collect::<Vec<String>>()
+			.join("\n");
 		Ok(CommandResult::Output(result))
 	}
 	
@@ -191,6 +466,64 @@ impl RuntimeCommand {
 		Ok(CommandResult::Output(format!("\n{vars}")))
 	}
 
+	/// Handles an [`Unlock`](RuntimeCommand::Unlock) command.
+	/// 
+	/// The change isn't recorded in the history, so going back doesn't reverse it.
+	/// The current prompt is displayed again so that newly usable choices appear.
+	fn unlock(player: &mut Player, target: &UnlockTarget, info_pages: &InfoPages) -> Result<CommandResult> {
+		use UnlockTarget::*;
+		let message = match target {
+			Note { name } => {
+				player.apply_note(name, false, false)?;
+				format!("Applied note '{name}'")
+			},
+			Info { page } => {
+				if !info_pages.contains_key(page) {
+					return Err(anyhow!("Invalid info page '{page}'"));
+				}
+				player.info_pages.insert(page.clone());
+				format!("Unlocked info page '{page}'")
+			},
+			Variable { name, value } => {
+				let value = value.join(" ");
+				let message = format!("Set variable '{name}' to '{value}'");
+				player.variables.insert(name.clone(), value);
+				message
+			}
+		};
+		println!("\n{message}");
+		Ok(CommandResult::Submit(GameLoopResult::Continue))
+	}
+
+	/// Handles an [`ImportSave`](RuntimeCommand::ImportSave) command.
+	/// 
+	/// The current player data is only replaced once the bundle has been validated,
+	/// and the imported prompt is displayed afterwards.
+	fn import_save(player: &mut Player, config: &Manifest, resources: &Resources, file: &Path) -> Result<CommandResult> {
+		let mut imported = SaveBundle::import(config, resources, file)?;
+		imported.record_playtime();
+		*player = imported;
+		println!("\nImported save from '{}'", file.display());
+		Ok(CommandResult::Submit(GameLoopResult::Continue))
+	}
+
+	/// Handles a [`Copy`](RuntimeCommand::Copy) command.
+	/// 
+	/// The player data is serialized the same way as in save files so that it can be pasted into bug reports.
+	/// The clipboard is kept in [`CLIPBOARD`] for the rest of the session, since on some platforms,
+	/// such as X11 without a clipboard manager, copied data is only available while it is alive.
+	fn copy(player: &Player) -> Result<CommandResult> {
+		let content = serde_yaml::to_string(player)?;
+		let mut clipboard = CLIPBOARD.lock().unwrap();
+		if clipboard.is_none() {
+			*clipboard = Some(arboard::Clipboard::new()
+				.map_err(|err| anyhow!("No clipboard available: {err}"))?);
+		}
+		clipboard.as_mut().unwrap().set_text(content)
+			.map_err(|err| anyhow!("Failed to copy to the clipboard: {err}"))?;
+		Ok(CommandResult::Output("Copied player data to the clipboard".to_owned()))
+	}
+
 	/// Executes a runtime command if the player has permission to do so.
 	///
 	/// Any errors will be reported to the input loop with a retry following.
@@ -201,19 +534,35 @@ impl RuntimeCommand {
 		use RuntimeCommand::*;
 		use CommandResult::*;
 		let result = match self {
+			Help => Self::help(config.settings.debug)?,
 			Back => Self::back(player)?,
-			Lang => Self::lang(player, &resources.translations)?,
+			Lang { lang } => Self::lang(player, lang, &resources.translations)?,
 			Info => Self::info(&player.info_pages, &resources.info_pages)?,
 			Log => Self::log(&player)?,
+			Journal => Self::journal(player)?,
 			Sound => Self::sound(player, &resources.audio)?,
+			Settings => Self::settings(player, config, resources)?,
+			Pause { channel } => Self::pause(&resources.audio, channel, false)?,
+			Resume { channel } => Self::pause(&resources.audio, channel, true)?,
+			Where => Self::where_(player, resources, text_context, config.settings.debug)?,
+			About { choice } => Self::about(player, resources, text_context, choice)?,
 			Save => {
 				saves.write(player, None, false)?;
 				Output("Saving... ".to_owned())
 			}
+			ExportSave { file } => {
+				SaveBundle::export(config, player, file)?;
+				Output(format!("Exported save to '{}'", file.display()))
+			},
+			ImportSave { file } => Self::import_save(player, config, resources, file)?,
 			Quit => Submit(GameLoopResult::Shutdown(false)),
-			Prompt => Self::prompt(&player.notes, resources, text_context)?,
+			Prompt => Self::prompt(resources, text_context)?,
+			Debug => Self::debug(player, resources, text_context)?,
+			&Map { depth } => Self::map(player, resources, depth)?,
 			Notes => Self::notes(player)?,
-			Variables => Self::variables(player)?
+			Variables => Self::variables(player)?,
+			Copy => Self::copy(player)?,
+			Unlock { target } => Self::unlock(player, target, &resources.info_pages)?
 		};
 		Ok(result)
 	}
@@ -1,18 +1,54 @@
-use std::{collections::{HashMap, BTreeMap}, time::Duration};
+use std::{collections::{HashMap, BTreeSet}, time::Duration, cell::{RefCell, OnceCell}, path::PathBuf, rc::Rc};
 
 use anyhow::{Result, anyhow};
 use playback_rs::{Player as AudioPlayer, Song};
 use result::OptionResultExt;
 use rlua::{Context, Table};
 
-use crate::{loading::base::Loader, text::context::TextContext};
+use crate::{loading::{base::Loader, assets::{AssetFiles, AssetKind}}, text::context::TextContext};
 
 use super::{manifest::Manifest, choice::{SoundAction, SoundActionMode}, player::Player};
 
-/// A map of channel names to audio player instances and whether they are currently enabled.
-pub type AudioPlayers = HashMap<String, AudioPlayer>;
-/// A map of song names to decoded song content.
-pub type Sounds = BTreeMap<String, Song>;
+/// A map of channel names to audio player instances.
+pub type AudioPlayers = HashMap<String, Rc<AudioPlayer>>;
+/// A map of song names to song content, decoded on startup or upon first play.
+pub type Sounds = AssetFiles<LazySound>;
+
+/// A sound file that is decoded either upon loading or upon first play.
+pub struct LazySound {
+	path: PathBuf,
+	song: OnceCell<Song>
+}
+
+impl LazySound {
+	/// Returns the decoded song, decoding and caching it first if necessary.
+	pub fn get(&self) -> Result<&Song> {
+		if let Some(song) = self.song.get() {
+			return Ok(song);
+		}
+		let song = Song::from_file(&self.path, None)
+			.map_err(|err| anyhow!(err))?;
+		Ok(self.song.get_or_init(|| song))
+	}
+}
+
+#[derive(Default, Debug)]
+/// The names of the sounds known to be started on a channel.
+struct Tracks {
+	/// The sound currently playing, if any.
+	current: Option<String>,
+	/// The sound queued to play after the current one, if any.
+	next: Option<String>
+}
+
+#[derive(Clone, Debug)]
+/// A snapshot of a channel's playback state exposed to scripts.
+pub struct ChannelState {
+	/// Whether the channel has a sound that is not paused.
+	pub playing: bool,
+	/// The name of the sound on the channel, if any.
+	pub track: Option<String>
+}
 
 /// A container for [`AudioPlayers`] and [`Sounds`].
 /// 
@@ -20,46 +56,81 @@ pub type Sounds = BTreeMap<String, Song>;
 /// wherein one sound file can be playing at a time. Overlapping sounds requires multiple connections
 /// and playing on different channels.
 /// 
-/// Channels are only created on startup. They are never dynamically loaded and must
-/// be specified in the manifest file prior to runtime.
+/// Declared channels are created on startup. If channels are enabled by default,
+/// a channel that isn't declared in the manifest is created upon its first sound action.
 pub struct Audio {
+	/// The players of the channels declared in the manifest.
 	pub players: AudioPlayers,
-	pub sounds: Sounds
+	/// The players of the channels created upon use since they weren't declared.
+	undeclared: RefCell<AudioPlayers>,
+	/// Whether channels are enabled unless specified otherwise. See [`Settings::channels_enabled_by_default`](super::manifest::Settings::channels_enabled_by_default).
+	default_enabled: bool,
+	pub sounds: Sounds,
+	/// The channels paused with [`SoundActionMode::Pause`] that have yet to be resumed.
+	paused: RefCell<BTreeSet<String>>,
+	/// The sounds started on each channel, since [`AudioPlayer`]s don't track sound names.
+	tracks: RefCell<HashMap<String, Tracks>>
 }
 
 impl Audio {
 	/// Creates [`AudioPlayers`]s and maps them to the config settings' `channels`.
+	/// 
+	/// Returns [`None`] if no channels are declared and channels aren't enabled by default.
 	fn load_players(config: &Manifest) -> Option<Result<AudioPlayers>> {
-		config.settings.channels.as_ref().map(|channels| {
-			channels.iter()
-    			.map(|(channel, _)| {
-					AudioPlayer::new(None)
-						.map(|player| (channel.clone(), player))
-    					.map_err(|err| anyhow!(err))
-				})
+		let settings = &config.settings;
+		(settings.channels.is_some() || settings.channels_enabled_by_default()).then(|| {
+			settings.declared_channels().into_iter()
+    			.map(|channel| Ok((channel.clone(), Self::open_player()?)))
         		.try_collect()
 		})
 	}
 
-	/// Loads and parses [`Sounds`] from the `sounds` directory.
-	fn load_sounds(loader: &Loader) -> Result<Sounds> {
-		loader.map_content("sounds", |path| {
-			Song::from_file(path, None)
-				.map_err(|err| anyhow!(err))
+	/// Opens a connection to the default sound device.
+	fn open_player() -> Result<Rc<AudioPlayer>> {
+		AudioPlayer::new(None)
+			.map(Rc::new)
+			.map_err(|err| anyhow!(err))
+	}
+
+	/// Locates the sound files using [`AssetFiles`]. If `preload` is `true`, every file is also decoded immediately,
+	/// reporting progress per file and, with the `parallel` feature, decoding across multiple threads.
+	/// 
+	/// A file that fails to preload is reported and left to be decoded again when played.
+	fn load_sounds(loader: &Loader, preload: bool) -> Result<Sounds> {
+		AssetFiles::map(loader, AssetKind::Sound, |path| {
+			let sound = LazySound { path, song: OnceCell::new() };
+			if preload {
+				if let Err(err) = sound.get() {
+					log::warn!("Failed to preload sound '{}': {err}", sound.path.display());
+				}
+			}
+			Ok(sound)
 		})
 	}
 
 	/// Loads an [`Audio`] container.
 	/// 
-	/// If [`AudioPlayer`] creation using [`load_players`](Self::load_players) fails, it fails silently
-	/// and brings the down the whole audio system with it, signaling [None] within the wrapped option.
+	/// If [`AudioPlayer`] creation using [`load_players`](Self::load_players) fails, such as when no output device
+	/// is available, a warning is printed and the whole audio system is brought down with it, signaling [None] within the wrapped option.
 	/// 
 	/// An [`Err`] is only returned if [`load_sounds`](Self::load_sounds) errors.
+	/// If the `audio` setting is disabled, nothing is loaded.
 	pub fn load(loader: &Loader, config: &Manifest) -> Result<Option<Self>> {
+		if !config.settings.audio {
+			log::info!("Audio is disabled");
+			return Ok(None);
+		}
 		Self::load_players(config).map(|result| {
-			result.ok().map(|players| {
-				Self::load_sounds(loader).map(|sounds| {
-					Self { players, sounds }
+			result.map_err(|err| log::warn!("Failed to initialize audio, continuing without sound: {err}")).ok().map(|players| {
+				Self::load_sounds(loader, config.settings.preload_sounds).map(|sounds| {
+					Self {
+						players,
+						undeclared: RefCell::new(HashMap::new()),
+						default_enabled: config.settings.channels_enabled_by_default(),
+						sounds,
+						paused: RefCell::new(BTreeSet::new()),
+						tracks: RefCell::new(HashMap::new())
+					}
 				})
 			})
 		})
@@ -67,10 +138,35 @@ impl Audio {
 		.invert()
 	}
 
-	/// Retrieves an [`AudioPlayer`], if any, by a channel name.
-	pub fn get_player(&self, channel: &str) -> Result<&AudioPlayer> {
+	/// Retrieves an [`AudioPlayer`] that has already been created, if any, by a channel name.
+	fn find_player(&self, channel: &str) -> Option<Rc<AudioPlayer>> {
 		self.players.get(channel)
-    		.ok_or(anyhow!("Invalid sound channel '{channel}'"))
+			.or(self.undeclared.borrow().get(channel))
+			.cloned()
+	}
+
+	/// Retrieves an [`AudioPlayer`] by a channel name.
+	/// 
+	/// If the channel isn't declared but channels are enabled by default, its player is created upon first use.
+	pub fn get_player(&self, channel: &str) -> Result<Rc<AudioPlayer>> {
+		if let Some(player) = self.find_player(channel) {
+			return Ok(player);
+		}
+		if !self.default_enabled {
+			return Err(anyhow!("Invalid sound channel '{channel}'"));
+		}
+		let player = Self::open_player()?;
+		self.undeclared.borrow_mut().insert(channel.to_owned(), player.clone());
+		Ok(player)
+	}
+
+	/// Whether sound actions apply to a channel. Declared channels must be among the player's `enabled` channels,
+	/// while undeclared channels follow the default.
+	pub fn is_enabled(&self, enabled: &BTreeSet<String>, channel: &str) -> bool {
+		match self.players.contains_key(channel) {
+			true => enabled.contains(channel),
+			false => self.default_enabled
+		}
 	}
 
 	/// Returns this controller's channel names mapped to whether they are enabled on the [`Player`].
@@ -104,29 +200,120 @@ impl Audio {
 		Ok(table)
 	}
 
+	/// Returns the name of the sound playing on a channel, if any.
+	/// 
+	/// A queued sound is considered current once the player no longer has a next sound.
+	pub fn current_track(&self, channel: &str) -> Option<String> {
+		let player = self.find_player(channel)?;
+		let mut tracks = self.tracks.borrow_mut();
+		let tracks = tracks.entry(channel.to_owned()).or_default();
+		if tracks.next.is_some() && !player.has_next_song() {
+			tracks.current = tracks.next.take();
+		}
+		if !player.has_current_song() {
+			tracks.current = None;
+		}
+		tracks.current.clone()
+	}
+
+	/// Captures the [`ChannelState`] of every channel.
+	pub fn channel_states(&self) -> HashMap<String, ChannelState> {
+		self.players.iter()
+			.map(|(channel, player)| {
+				let state = ChannelState {
+					playing: player.has_current_song() && player.is_playing(),
+					track: self.current_track(channel)
+				};
+				(channel.clone(), state)
+			})
+			.collect()
+	}
+
+	/// Transitions from the music of the previously entered prompt to the `music` of a newly entered prompt.
+	/// 
+	/// If both name the same track on the same channel and it is still playing, it continues uninterrupted.
+	/// Otherwise, the previous music's channel is stopped and the new music, if any and its `when` condition is met, is applied.
+	/// 
+	/// Returns the channel and track of the new music, to be passed as `previous` upon the next transition.
+	pub fn transition_music(&self, enabled: &BTreeSet<String>, previous: Option<&(String, String)>, music: Option<&SoundAction>, text_context: &TextContext) -> Result<Option<(String, String)>> {
+		let music = match music {
+			Some(action) if action.when.as_ref().map(|when| when.evaluate(text_context)).invert()?.unwrap_or(true) => Some(action),
+			_ => None
+		};
+		let next = music
+			.map(|action| -> Result<(String, String)> {
+				let name = action.name.as_ref().ok_or(anyhow!("Prompt music must name a sound"))?;
+				Ok((action.channel.fill(text_context)?, name.fill(text_context)?))
+			})
+			.invert()?;
+		if let Some((channel, track)) = &next {
+			if previous == next.as_ref() && self.current_track(channel).as_ref() == Some(track) {
+				return Ok(next);
+			}
+		}
+		if let Some((channel, _)) = previous {
+			self.get_player(channel)?.stop();
+		}
+		if let Some(action) = music {
+			self.accept(enabled, action, text_context)?;
+		}
+		Ok(next)
+	}
+
+	/// Pauses a channel's current sound, if it is playing one, and remembers it as paused.
+	pub fn pause(&self, channel: &str) -> Result<()> {
+		let player = self.find_player(channel)
+			.ok_or(anyhow!("Invalid sound channel '{channel}'"))?;
+		if player.has_current_song() && player.is_playing() {
+			player.set_playing(false);
+			self.paused.borrow_mut().insert(channel.to_owned());
+		}
+		Ok(())
+	}
+
+	/// Resumes a channel previously paused with [`Audio::pause`]. Resuming any other channel does nothing.
+	pub fn resume(&self, channel: &str) -> Result<()> {
+		let player = self.find_player(channel)
+			.ok_or(anyhow!("Invalid sound channel '{channel}'"))?;
+		if self.paused.borrow_mut().remove(channel) {
+			player.set_playing(true);
+		}
+		Ok(())
+	}
+
 	/// Applies actions requiring that a specified sound file is **not** present.
-	fn accept_general_actions(player: &AudioPlayer, seek: Option<Duration>, mode: SoundActionMode) {
+	fn accept_general_actions(&self, channel: &str, player: &AudioPlayer, seek: Option<Duration>, mode: SoundActionMode) -> Result<()> {
 		use SoundActionMode::*;
 		if let Some(duration) = seek {
 			player.seek(duration);
 		}
 		match mode {
-			Skip => player.skip(),
+			Skip => {
+				player.skip();
+				let mut tracks = self.tracks.borrow_mut();
+				let tracks = tracks.entry(channel.to_owned()).or_default();
+				tracks.current = tracks.next.take();
+			},
 			Playing => player.set_playing(true),
 			Paused => player.set_playing(false),
+			Pause => self.pause(channel)?,
+			Resume => self.resume(channel)?,
 			_ => ()
 		}
+		Ok(())
 	}
 
-	/// Applies actions requiring both a [`SoundActionMode`] and accompanying sound effect.
-	fn accept_mode(player: &AudioPlayer, sfx: &Song, seek: Option<Duration>, mode: SoundActionMode) {
+	/// Applies actions requiring both a [`SoundActionMode`] and accompanying sound effect, recording the sound's name.
+	fn accept_mode(&self, channel: &str, player: &AudioPlayer, name: &str, sfx: &Song, seek: Option<Duration>, mode: SoundActionMode) {
 		use SoundActionMode::*;
+		let mut tracks = self.tracks.borrow_mut();
+		let tracks = tracks.entry(channel.to_owned()).or_default();
 		let _ = match mode {
-			Queue => player.play_song_next(sfx, seek),
-			Overwrite => player.play_song_now(sfx, seek),
+			Queue => player.play_song_next(sfx, seek).map(|_| tracks.next = Some(name.to_owned())),
+			Overwrite => player.play_song_now(sfx, seek).map(|_| tracks.current = Some(name.to_owned())),
 			Passive => { 
 				if !player.has_current_song() {
-					player.play_song_now(sfx, seek)
+					player.play_song_now(sfx, seek).map(|_| tracks.current = Some(name.to_owned()))
 				}
 				else {
 					Ok(())
@@ -137,13 +324,20 @@ impl Audio {
 	}
 
 	/// Applies a [`SoundAction`] to a particular channel.
-	pub fn accept(&self, player: &Player, action: &SoundAction, text_context: &TextContext) -> Result<()> {
+	/// 
+	/// The action is ignored if its `when` condition isn't met or the channel isn't [enabled](Audio::is_enabled).
+	pub fn accept(&self, enabled: &BTreeSet<String>, action: &SoundAction, text_context: &TextContext) -> Result<()> {
+		if let Some(when) = &action.when {
+			if !when.evaluate(text_context)? {
+				return Ok(());
+			}
+		}
+
 		let channel = action.channel.fill(text_context)?;
-		let audio_player = self.get_player(&channel)?;
-		
-		if !player.channels.contains(&channel) {
+		if !self.is_enabled(enabled, &channel) {
 			return Ok(());
 		}
+		let audio_player = self.get_player(&channel)?;
 
 		let seek = action.seek.as_ref().map(|ms| {
 			ms.get_value(text_context).map(|amt| Duration::from_millis(amt))
@@ -152,12 +346,11 @@ impl Audio {
 		let mode = action.mode.get_value(text_context)?;
 
 		match &action.name {
-			None => Self::accept_general_actions(audio_player, seek, mode),
+			None => self.accept_general_actions(&channel, &audio_player, seek, mode)?,
 			Some(name) => {
 				let sound = name.fill(text_context)?;
-				let sfx = self.sounds.get(&sound)
-					.ok_or(anyhow!("Invalid sound file '{sound}'"))?;
-				Self::accept_mode(audio_player, sfx, seek, mode);
+				let sfx = self.sounds.get(&sound)?.get()?;
+				self.accept_mode(&channel, &audio_player, &sound, sfx, seek, mode);
 			}
 		}
 
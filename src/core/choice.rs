@@ -1,22 +1,24 @@
-use std::{collections::{HashMap, HashSet}};
+use std::{collections::{HashMap, HashSet, BTreeMap, BTreeSet}, fmt::Display};
 
-use crate::{game::input::VariableInputResult, text::{templating::{TemplatableValue, TemplatableString}, context::TextContext, display::{Text, TextLines}}};
+use crate::{game::input::VariableInputResult, text::{templating::{TemplatableValue, TemplatableString, TemplatableBool}, context::TextContext, display::{Text, TextLines}, conditions::{Condition, Operand, is_truthy}}};
 
-use super::{path::Path, prompt::{Prompts, Prompt, PromptModel}, player::{HistoryEntry, VariableEntry, VariableEntries, NoteEntry, NoteEntries}, manifest::Manifest};
+use super::{path::Path, prompt::{Prompts, Prompt, PromptModel}, player::{HistoryEntry, VariableEntry, VariableEntries, NoteEntry, NoteEntries}, scripts::Scripts, manifest::Manifest};
 
 use anyhow::{Result, anyhow, Context};
 use result::OptionResultExt;
+use crossterm::style::Stylize;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use strum::{EnumString, Display};
 
-pub fn default_true() -> TemplatableValue<bool> { TemplatableValue::value(true) }
+pub fn default_true() -> TemplatableBool { TemplatableBool::value(true) }
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct NoteApplication {
 	pub name: TemplatableString,
 	#[serde(default)]
-	pub take: TemplatableValue<bool>,
+	pub take: TemplatableBool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -24,7 +26,7 @@ pub struct NoteApplication {
 pub struct NoteRequirement {
 	name: TemplatableString,
 	#[serde(default = "default_true")]
-	has: TemplatableValue<bool>
+	has: TemplatableBool
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -52,22 +54,129 @@ impl NoteActions {
 	}
 }
 
-/// A list of string symbols tracked on a player.
-pub type Notes = HashSet<String>;
+/// A sorted set of string symbols tracked on a player.
+/// 
+/// This is ordered so that saves serialize deterministically.
+/// Names may be namespaced with a `:` separator, such as `chapter2:met_guard`; see [`note_namespace`].
+pub type Notes = BTreeSet<String>;
+
+/// The separator between a note's namespace and its name.
+pub const NOTE_NAMESPACE_SEPARATOR: char = ':';
+
+/// Splits a note into its namespace, if any, and its name.
+pub fn note_namespace(note: &str) -> (Option<&str>, &str) {
+	match note.split_once(NOTE_NAMESPACE_SEPARATOR) {
+		Some((namespace, name)) => (Some(namespace), name),
+		None => (None, note)
+	}
+}
+
+/// Counts the notes matching a query. A query ending in the namespace separator, such as `chapter2:`,
+/// matches every note under that namespace; any other query matches a single note by name.
+pub fn count_notes(notes: &Notes, query: &str) -> usize {
+	if !query.ends_with(NOTE_NAMESPACE_SEPARATOR) {
+		return usize::from(notes.contains(query));
+	}
+	notes.range(query.to_owned()..)
+		.take_while(|note| note.starts_with(query))
+		.count()
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct VariableInput {
 	pub text: Option<TemplatableString>,
 	#[serde(rename = "variable")]
-	pub name: TemplatableString
+	pub name: TemplatableString,
+	/// The value used when the player leaves the input empty. Without one, empty input is rejected.
+	pub default: Option<TemplatableString>,
+	/// The maximum amount of characters the player can enter.
+	pub max_length: Option<usize>
 }
 
-/// A map of display variables wherein the key is the variable name and the value is the variable's display.
-pub type Variables = HashMap<String, String>;
+/// A sorted map of display variables wherein the key is the variable name and the value is the variable's display.
+/// 
+/// This is ordered so that saves serialize deterministically.
+pub type Variables = BTreeMap<String, String>;
 
 pub type VariableApplications = HashMap<String, TemplatableString>;
 
+/// A map of numeric variable names to the amounts deducted from them when a choice is taken, such as `gold: 5`.
+pub type ChoiceCost = BTreeMap<String, TemplatableValue<f64>>;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A variable value holding an ordered list of strings.
+/// 
+/// Lists are stored within [`Variables`] as a JSON array behind [`VariableList::MARKER`], such as `\u{1E}["sword","shield"]`.
+/// The marker is a control character, which player input can never contain, so no plain string is mistaken for a list.
+pub struct VariableList(pub Vec<String>);
+
+impl VariableList {
+	/// The character prefixing every serialized list.
+	pub const MARKER: char = '\u{1E}';
+
+	/// Parses a serialized variable value as a list, if it is one.
+	pub fn parse(value: &str) -> Option<Self> {
+		let values = value.strip_prefix(Self::MARKER)?;
+		serde_json::from_str(values).ok().map(Self)
+	}
+
+	/// Parses a variable value as a list, treating an unset variable as an empty list.
+	pub fn from_variable(name: &str, value: Option<&String>) -> Result<Self> {
+		match value {
+			None => Ok(Self(Vec::new())),
+			Some(value) => Self::parse(value).ok_or(anyhow!("Variable '{name}' is not a list"))
+		}
+	}
+
+	/// Serializes this list into its stored variable form.
+	pub fn serialize(&self) -> String {
+		let values = serde_json::to_string(&self.0).unwrap();
+		format!("{}{values}", Self::MARKER)
+	}
+
+	/// Retrieves an element by index, erroring if the index is out of bounds.
+	pub fn get(&self, name: &str, index: usize) -> Result<&String> {
+		self.0.get(index)
+			.ok_or(anyhow!("Index {index} is out of bounds for list '{name}' of length {}", self.0.len()))
+	}
+}
+
+impl Display for VariableList {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0.join(", "))
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+/// Modifications to a list variable. Additions are applied before removals.
+pub struct ListApplication {
+	/// Values to append to the end of the list.
+	pub add: Option<Vec<TemplatableString>>,
+	/// Values to remove from the list. Only the first occurrence of each value is removed.
+	pub remove: Option<Vec<TemplatableString>>
+}
+
+impl ListApplication {
+	/// Applies this modification to the current value of a list variable, returning the new serialized value.
+	pub fn apply(&self, name: &str, current: Option<&String>, text_context: &TextContext) -> Result<String> {
+		let mut list = VariableList::from_variable(name, current)?;
+		for value in self.add.iter().flatten() {
+			list.0.push(value.fill(text_context)?);
+		}
+		for value in self.remove.iter().flatten() {
+			let value = value.fill(text_context)?;
+			if let Some(index) = list.0.iter().position(|v| v == &value) {
+				list.0.remove(index);
+			}
+		}
+		Ok(list.serialize())
+	}
+}
+
+pub type ListApplications = HashMap<String, ListApplication>;
+
 #[derive(Deserialize, Serialize, Display, Debug, Clone, EnumString)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
@@ -77,7 +186,11 @@ pub enum SoundActionMode {
 	Passive,
 	Skip,
 	Playing,
-	Paused
+	Paused,
+	/// Pauses the channel's current sound so that it can be resumed from the same position.
+	Pause,
+	/// Resumes the channel if it was paused with [`SoundActionMode::Pause`], otherwise does nothing.
+	Resume
 }
 
 impl Default for SoundActionMode {
@@ -94,7 +207,9 @@ pub struct SoundAction {
 	#[serde(default)]
 	pub mode: TemplatableValue<SoundActionMode>,
 	pub seek: Option<TemplatableValue<u64>>,
-	pub speed: Option<TemplatableValue<f64>>
+	pub speed: Option<TemplatableValue<f64>>,
+	/// A condition that must be met for the action to be applied.
+	pub when: Option<Condition>
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -102,32 +217,115 @@ pub struct SoundAction {
 pub struct Choice {
 	pub response: Option<Text>,
 	tag: Option<TemplatableString>,
+	/// A label under which consecutive choices with the same group are displayed. Purely cosmetic.
+	pub group: Option<TemplatableString>,
+	/// Details about this choice that aren't displayed in the list, viewed with the `about` command.
+	pub description: Option<TemplatableString>,
+	/// A word that selects this choice when typed in place of its label, ignoring case.
+	pub keyword: Option<TemplatableString>,
+	#[serde(default)]
+	/// Whether to leave this choice out of the displayed choices, so that it can only be selected by its `keyword`.
+	/// A prompt's only choice and choices taking variable input can't be hidden.
+	pub hidden: bool,
 	pub input: Option<VariableInput>,
+	/// Several variable inputs taken in sequence before the choice is taken, such as for character creation.
+	pub inputs: Option<Vec<VariableInput>>,
 	pub jump: Option<Path>,
 	#[serde(default = "default_true")]
-	pub display: TemplatableValue<bool>,
+	pub display: TemplatableBool,
 	// This is an option for easier defaulting to the config state
-	pub lock: Option<TemplatableValue<bool>>,
+	pub lock: Option<TemplatableBool>,
 	pub notes: Option<NoteActions>,
+	pub when: Option<Condition>,
+	/// The game versions this choice is available in, such as `>=1.2`. The choice is hidden in other versions.
+	pub version: Option<VersionReq>,
+	#[serde(default)]
+	/// Whether to display this choice, dimmed and unselectable, while the player can't use it.
+	pub show_locked: bool,
+	/// The reason displayed next to this choice while it is locked. Defaults to [`Choice::DEFAULT_LOCKED_HINT`].
+	pub locked_hint: Option<TemplatableString>,
+	#[serde(default)]
+	/// The ordering weight of this choice when its prompt is sorted by priority; higher comes first.
+	pub priority: i32,
 	pub variables: Option<VariableApplications>,
+	pub lists: Option<ListApplications>,
+	/// The amounts deducted from numeric variables when this choice is taken. The choice is hidden while the player can't afford it.
+	pub cost: Option<ChoiceCost>,
+	/// A script run when the choice is taken. See [`Player::choose`](super::player::Player::choose) for the order of effects.
+	pub on_select: Option<String>,
 	pub log: Option<TemplatableString>,
 	#[serde(rename = "info")]
 	pub info_pages: Option<Vec<TemplatableString>>,
+	/// The info pages the player must have unlocked to use this choice.
+	pub info_required: Option<Vec<TemplatableString>>,
 	pub sounds: Option<Vec<SoundAction>>,
-	pub ending: Option<TextLines>
+	pub ending: Option<TextLines>,
+	#[serde(default)]
+	/// Whether to offer restarting, loading a save, or quitting after the `ending` instead of exiting immediately.
+	pub ending_menu: bool
 }
 
 pub type Choices = Vec<Choice>;
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// The scheme used to label choices on display and to select them on input.
+pub enum ChoiceLabels {
+	#[default]
+	/// Labels choices `1`, `2`, `3`, and so on.
+	Numbers,
+	/// Labels choices `a`, `b`, `c`, and so on, using numbers instead past 26 choices.
+	Letters
+}
+
+impl ChoiceLabels {
+	/// Whether letters are used for a certain amount of choices.
+	fn uses_letters(&self, total: usize) -> bool {
+		*self == ChoiceLabels::Letters && total <= 26
+	}
+
+	/// Creates the label of the choice at a 1-based index out of `total` choices.
+	pub fn label(&self, index: usize, total: usize) -> String {
+		if self.uses_letters(total) {
+			return ((b'a' + (index - 1) as u8) as char).to_string();
+		}
+		index.to_string()
+	}
+
+	/// Parses a label back into a 1-based choice index out of `total` choices.
+	pub fn parse(&self, input: &str, total: usize) -> Result<usize> {
+		let index = if self.uses_letters(total) {
+			let mut chars = input.chars();
+			match (chars.next(), chars.next()) {
+				(Some(c), None) if c.is_ascii_alphabetic() => (c.to_ascii_lowercase() as u8 - b'a') as usize + 1,
+				_ => return Err(anyhow!("Input must be a letter"))
+			}
+		}
+		else {
+			input.parse::<usize>()
+				.map_err(|_| anyhow!("Input must be a number"))?
+		};
+		if index < 1 || index > total {
+			return Err(anyhow!("Input out of range"))
+		}
+		Ok(index)
+	}
+}
+
 impl Choice {
 	/// Validates a choice amongst the global prompt context.
 	/// 
 	/// A choice is valid if:
 	/// - It has either a `jump` or `ending` section
 	/// - Its `jump` section **is not templatable** and points to a valid prompt
-	/// 	- The `file` key has to exist and the `prompt` key has to exist in that [`PromptFile`]
+	/// 	- The `prompt` key has to exist in the [`PromptFile`] of the `file` key, or the local file if omitted
 	/// - It has a `response` section if there is more than one choice in the prompt
-	pub fn validate(&self, local_file: &String, has_company: bool, prompts: &Prompts) -> Result<()> {
+	/// - It has an `ending` section if `ending_menu` is enabled
+	/// - It doesn't have both `input` and `inputs` sections
+	/// - Its `when` condition only calls methods on `notes` or declared variables
+	/// - Its `cost` only references declared variables
+	/// - Its `on_select` script, if any, exists
+	pub fn validate(&self, local_file: &String, has_company: bool, prompts: &Prompts, scripts: &Scripts, variables: &HashSet<&str>) -> Result<()> {
 		match &self.jump {
 			None => if self.ending.is_none() {
 				return Err(anyhow!("Lacks `jump` section, but doesn't have an `ending` section"))
@@ -136,47 +334,171 @@ impl Choice {
 				if jump.is_not_templatable() {
 					let file = jump.file.as_ref().map(|t| t.content.clone())
 						.unwrap_or(local_file.clone());
-					let _ = Prompt::get(prompts, &jump.prompt.content, &file)
-						.with_context(|| "`jump` section points to invalid prompt")?;
+					let result = Prompt::get(prompts, &jump.prompt.content, &file);
+					if result.is_err() && jump.file.is_none() {
+						// The author may have meant a prompt in another file
+						let candidates = Prompt::files_containing(prompts, &jump.prompt.content)?;
+						if !candidates.is_empty() {
+							let candidates = itertools::join(candidates, "', '");
+							return Err(anyhow!("`jump` section omits `file`, but prompt '{}' is not in file '{file}'; it was found in '{candidates}'", jump.prompt.content));
+						}
+					}
+					let _ = result.with_context(|| "`jump` section points to invalid prompt")?;
 				}
 			},
 		}
+		if let Some(script) = &self.on_select {
+			if !scripts.contains(script) {
+				return Err(anyhow!("`on_select` references invalid script '{script}'"))
+			}
+		}
 		if has_company && self.response.is_none() {
 			return Err(anyhow!("Lacks `response` section, but multiple choices are present in prompt"))
 		}
+		if self.input.is_some() && self.inputs.is_some() {
+			return Err(anyhow!("Has both `input` and `inputs` sections"))
+		}
+		if self.hidden && self.keyword.is_none() {
+			return Err(anyhow!("Is `hidden`, but doesn't have a `keyword` to select it by"))
+		}
+		if self.hidden && !self.input_fields().is_empty() {
+			return Err(anyhow!("Is `hidden`, but takes variable input, which can't be given by `keyword`"))
+		}
+		if self.hidden && !has_company {
+			return Err(anyhow!("Is `hidden`, but is the only choice in prompt"))
+		}
+		if self.ending_menu && self.ending.is_none() {
+			return Err(anyhow!("Enables `ending_menu`, but doesn't have an `ending` section"))
+		}
+		let conditions = [("when", self.when.as_ref()), ("display", self.display.condition()), ("lock", self.lock.as_ref().and_then(TemplatableBool::condition))];
+		for (key, condition) in conditions {
+			for collection in condition.iter().flat_map(|condition| condition.collections()) {
+				if collection != "notes" && !variables.contains(collection) {
+					return Err(anyhow!("`{key}` condition references unknown collection '{collection}'"))
+				}
+			}
+		}
+		for name in self.cost.iter().flat_map(|cost| cost.keys()) {
+			if !variables.contains(name.as_str()) {
+				return Err(anyhow!("`cost` references unknown variable '{name}'"))
+			}
+		}
 		Ok(())
 	}
 
+	/// Returns the non-templated names of all variables this choice can set.
+	pub fn declared_variables(&self) -> Vec<&str> {
+		let mut result: Vec<&str> = self.variables.iter().flat_map(|vars| vars.keys())
+			.chain(self.lists.iter().flat_map(|lists| lists.keys()))
+			.map(|name| name.as_str())
+			.collect();
+		for input in self.input_fields() {
+			if !input.name.is_templatable() {
+				result.push(&input.name.content);
+			}
+		}
+		result
+	}
+
+	/// The variable inputs taken before this choice, from either the `input` or `inputs` section.
+	pub fn input_fields(&self) -> Vec<&VariableInput> {
+		self.input.iter().chain(self.inputs.iter().flatten()).collect()
+	}
+
+	/// Collects the raw content of every string in this choice that is displayed to the player.
+	/// 
+	/// Any of these may be a translation key.
+	pub fn displayed_strings(&self) -> Vec<&str> {
+		self.response.iter()
+			.chain(self.ending.iter().flatten())
+			.map(|text| &text.content)
+			.chain(self.tag.iter())
+			.chain(self.group.iter())
+			.chain(self.description.iter())
+			.chain(self.locked_hint.iter())
+			.chain(self.input_fields().into_iter().flat_map(|input| input.text.iter()))
+			.chain(self.log.iter())
+			.map(|string| string.content.as_str())
+			.collect()
+	}
+
+	/// Collects this choice's sound actions, including those of its response and ending text.
+	pub fn sound_actions(&self) -> Vec<&SoundAction> {
+		self.response.iter()
+			.chain(self.ending.iter().flatten())
+			.filter_map(|text| text.sound.as_ref())
+			.chain(self.sounds.iter().flatten())
+			.collect()
+	}
+
+	/// Returns the text to add to the player's log when this choice is taken, if any.
+	/// 
+	/// This is the `log` key, if present. Otherwise, it is the response text, unless the `log_responses` setting is disabled.
+	pub fn log_entry(&self, config: &Manifest) -> Option<&TemplatableString> {
+		self.log.as_ref().or_else(|| {
+			self.response.as_ref()
+				.filter(|_| config.settings.log_responses)
+				.map(|response| &response.content)
+		})
+	}
+
 	/// Creates a map of variable entries to use when creating a new [`HistoryEntry`].
 	/// 
-	/// If both the input result and this choice's `variables` key are [`None`], returns none.
+	/// If there are no input results and this choice's `variables`, `lists`, and `cost` keys are all [`None`], returns none.
 	/// Otherwise, returns a combined map based on which inputs are present.
-	pub fn create_variable_entries(&self, input: Option<&VariableInputResult>, variables: &Variables, text_context: &TextContext) -> Result<Option<VariableEntries>> {
-		let input_entry = input.map(|result| result.to_variable_entry(variables));
-		let var_entries = self.variables.as_ref().map(|vars| VariableEntry::from_map(&vars, variables, text_context)).invert()?;
-		if input_entry.is_none() && var_entries.is_none() {
+	/// List modifications are applied on top of any `variables` entry for the same name, and `cost` deductions on top of both.
+	pub fn create_variable_entries(&self, inputs: &[VariableInputResult], text_context: &TextContext) -> Result<Option<VariableEntries>> {
+		let var_entries = self.variables.as_ref().map(|vars| VariableEntry::from_map(&vars, &text_context.variables, text_context)).invert()?;
+		if inputs.is_empty() && var_entries.is_none() && self.lists.is_none() && self.cost.is_none() {
 			return Ok(None);
 		}
-		let mut entries = var_entries.unwrap_or(HashMap::new());
-		if let Some((name, entry)) = input_entry {
+		let mut entries = var_entries.unwrap_or(BTreeMap::new());
+		for result in inputs {
+			let (name, entry) = result.to_variable_entry(&text_context.variables);
 			entries.insert(name.clone(), entry);
 		}
+		for (name, app) in self.lists.iter().flatten() {
+			let current = entries.get(name).map(|entry| &entry.value).or(text_context.variables.get(name));
+			let value = app.apply(name, current, text_context)?;
+			entries.insert(name.clone(), VariableEntry::new(name, value, &text_context.variables));
+		}
+		for (name, amount) in self.cost.iter().flatten() {
+			let current = entries.get(name).map(|entry| &entry.value).or(text_context.variables.get(name));
+			let value = Self::numeric_value(current) - amount.get_value(text_context)?;
+			entries.insert(name.clone(), VariableEntry::new(name, value.to_string(), &text_context.variables));
+		}
 		Ok(Some(entries))
 	}
 
+	/// Parses a variable value as a number, treating missing and non-numeric values as `0`.
+	fn numeric_value(value: Option<&String>) -> f64 {
+		value.and_then(|value| value.parse::<f64>().ok()).unwrap_or_default()
+	}
+
+	/// Returns the first `cost` the player can't afford, if any, formatted as a reason such as `Can't afford 5 gold`.
+	fn cost_reason(&self, text_context: &TextContext) -> Result<Option<String>> {
+		for (name, amount) in self.cost.iter().flatten() {
+			let amount = amount.get_value(text_context)?;
+			if Self::numeric_value(text_context.variables.get(name)) < amount {
+				return Ok(Some(format!("Can't afford {amount} {name}")));
+			}
+		}
+		Ok(None)
+	}
+
 	/// Constructs a [`HistoryEntry`] based on this choice object. 
 	/// 
 	/// Copies over control flags, the path based on the latest history entry, and notes and variable applications.
-	pub fn to_history_entry(&self, latest: &HistoryEntry, input: Option<&VariableInputResult>, config: &Manifest, variables: &Variables, model: &PromptModel, text_context: &TextContext) -> Option<Result<HistoryEntry>> {
+	pub fn to_history_entry(&self, latest: &HistoryEntry, inputs: &[VariableInputResult], model: &PromptModel, text_context: &TextContext) -> Option<Result<HistoryEntry>> {
 		self.jump.as_ref().map(|jump| {
 			Ok(HistoryEntry {
 				path: jump.fill(&latest.path, text_context)?,
 				display: self.display.get_value(text_context)?,
-				locked: self.lock.as_ref().map(|lock| lock.get_value(text_context)).invert()?.unwrap_or(config.settings.history.locked),
+				locked: self.lock.as_ref().map(|lock| lock.get_value(text_context)).invert()?.unwrap_or(text_context.config.settings.history.locked),
 				redirect: matches!(model, PromptModel::Redirect(_)),
 				notes: self.notes.as_ref().map(|n| n.to_note_entries(text_context)).invert()?,
-				variables: self.create_variable_entries(input, variables, text_context)?,
-				log: self.log.is_some()
+				variables: self.create_variable_entries(inputs, text_context)?,
+				log: self.log_entry(text_context.config).is_some()
 			})
 		})
 	}
@@ -184,24 +506,76 @@ impl Choice {
 	/// Determines if a player can use this choice.
 	/// 
 	/// This check passes if:
-	/// - All note requirement `has` fields match the state of the provided [`Notes`] object, and
-	/// - The notes object does not contain the `once` value, if any is present
-	pub fn can_player_use(&self, notes: &Notes, text_context: &TextContext) -> Result<bool> {
+	/// - The game version matches this choice's `version` and that of the prompt it jumps to, if either is present,
+	/// - The `when` condition, if any, evaluates to `true`,
+	/// - All note requirement `has` fields match the state of the context's [`Notes`] object,
+	/// - The notes object does not contain the `once` value, if any is present,
+	/// - Every `info_required` page has been unlocked, and
+	/// - The player's variables cover every `cost` amount
+	pub fn can_player_use(&self, text_context: &TextContext) -> Result<bool> {
+		Ok(self.gating_reason(text_context)?.is_none())
+	}
+
+	/// Resolves the prompt this choice jumps to, if any.
+	/// 
+	/// A relative jump can only be resolved when the context knows the current file, and an invalid jump is left to validation.
+	fn jump_target<'a>(&self, text_context: &TextContext<'a>) -> Result<Option<&'a Prompt>> {
+		let Some(jump) = &self.jump else {
+			return Ok(None);
+		};
+		let file = match (&jump.file, &text_context.file) {
+			(Some(file), _) => file.fill(text_context)?,
+			(None, Some(file)) => file.clone(),
+			(None, None) => return Ok(None)
+		};
+		Ok(Prompt::get(text_context.prompts, &jump.prompt.fill(text_context)?, &file).ok())
+	}
+
+	/// Returns why the player can't use this choice, if they can't. See [`Choice::can_player_use`] for the criteria.
+	pub fn gating_reason(&self, text_context: &TextContext) -> Result<Option<String>> {
+		if let Some(version) = &self.version {
+			let game_version = &text_context.config.metadata.version;
+			if !version.matches(game_version) {
+				return Ok(Some(format!("Requires game version '{version}', but the game is version '{game_version}'")));
+			}
+		}
+		if let Some(version) = self.jump_target(text_context)?.and_then(|prompt| prompt.version.as_ref()) {
+			let game_version = &text_context.config.metadata.version;
+			if !version.matches(game_version) {
+				return Ok(Some(format!("Leads to a prompt requiring game version '{version}', but the game is version '{game_version}'")));
+			}
+		}
+		if let Some(when) = &self.when {
+			if !when.evaluate(text_context)? {
+				return Ok(Some(format!("`when` condition '{when}' is false")));
+			}
+		}
+		let notes = &text_context.notes;
 		if let Some(actions) = &self.notes {
 			if let Some(require) = &actions.require {
 				for requirement in require {
-					if requirement.has.get_value(text_context)? != notes.contains(&requirement.name.fill(text_context)?) {
-						return Ok(false);
+					let name = requirement.name.fill(text_context)?;
+					let has = requirement.has.get_value(text_context)?;
+					if has != notes.contains(&name) {
+						let reason = if has { "Requires" } else { "Forbids" };
+						return Ok(Some(format!("{reason} note '{name}'")));
 					}
 				}
 			}
 			if let Some(once) = &actions.once {
-				if notes.contains(&once.fill(text_context)?) {
-					return Ok(false);
+				let name = once.fill(text_context)?;
+				if notes.contains(&name) {
+					return Ok(Some(format!("Already used; `once` note '{name}' is applied")));
 				}
 			}
 		}
-		Ok(true)
+		for page in self.info_required.iter().flatten() {
+			let name = page.fill(text_context)?;
+			if !text_context.info_pages.contains(&name) {
+				return Ok(Some(format!("Requires info page '{name}'")));
+			}
+		}
+		self.cost_reason(text_context)
 	}
 
 	/// Fills in and formats tag content, if any.
@@ -221,21 +595,136 @@ impl Choice {
 	/// ### Examples
 	/// 
 	/// - `1) [ROGUE] "Ain't no thief."`
-	/// - `2) Put down the sword`
-	fn response_line(&self, index: usize, text_context: &TextContext) -> Result<String> {
+	/// - `b) Put down the sword`
+	fn response_line(&self, label: &str, text_context: &TextContext) -> Result<String> {
 		let tag = self.tag(text_context)?;
 		let response = self.response.as_ref().unwrap().get(text_context)?;
-		Ok(format!("{index}) {tag}{response}"))
+		Ok(format!("{label}) {tag}{response}"))
 	}
 
-	/// Constructs a [`String`] of ordered choice responses.
-	pub fn display(choices: &Vec<&Choice>, text_context: &TextContext) -> Result<String> {
-		let result = choices.iter().enumerate()
-			.filter(|(_, choice)| choice.response.is_some())
-			.map(|(index, choice)| choice.response_line(index + 1, text_context))
-			.try_collect::<Vec<String>>()?
-			.join("\n");
-		Ok(result)
+	/// The label displayed in place of a locked choice's input label.
+	const LOCKED_LABEL: &'static str = "-";
+	/// The hint displayed next to a locked choice without a `locked_hint`.
+	pub const DEFAULT_LOCKED_HINT: &'static str = "Locked";
+
+	/// Formats the dimmed response line of a locked choice, followed by its `locked_hint`.
+	/// 
+	/// Without a `locked_hint`, an unaffordable `cost` is displayed instead of [`Choice::DEFAULT_LOCKED_HINT`].
+	fn locked_line(&self, text_context: &TextContext) -> Result<String> {
+		let hint = match &self.locked_hint {
+			Some(hint) => hint.fill(text_context)?,
+			None => self.cost_reason(text_context)?.unwrap_or(Self::DEFAULT_LOCKED_HINT.to_owned())
+		};
+		let line = self.response_line(Self::LOCKED_LABEL, text_context)?;
+		Ok(format!("{line} ({hint})").dark_grey().to_string())
+	}
+
+	/// Constructs a [`String`] of ordered choice responses, labeled according to the `choice_labels` setting.
+	/// 
+	/// Each `locked` choice follows the last usable choice in its group, or all usable choices if none share its group.
+	/// Locked choices are dimmed and without input labels.
+	/// 
+	/// Whenever the `group` changes between consecutive choices, a blank line and the new group's header, if any, are inserted.
+	/// Groups don't affect the order or labels of the usable choices.
+	pub fn display(choices: &Vec<&Choice>, locked: &[&Choice], text_context: &TextContext) -> Result<String> {
+		let labels = text_context.config.settings.choice_labels;
+		let group = |choice: &Choice| choice.group.as_ref().map(|group| group.fill(text_context)).invert();
+		let mut entries = Vec::new();
+		for (index, choice) in choices.iter().enumerate().filter(|(_, choice)| choice.response.is_some()) {
+			entries.push((Some(index), *choice, group(choice)?));
+		}
+		for choice in locked.iter().filter(|choice| choice.response.is_some()) {
+			let group = group(choice)?;
+			// Keeping locked choices within their group avoids repeating its header
+			let position = entries.iter()
+				.rposition(|(_, _, other)| *other == group)
+				.map_or(entries.len(), |position| position + 1);
+			entries.insert(position, (None, *choice, group));
+		}
+		let mut lines = Vec::new();
+		let mut current_group = None;
+		for (index, choice, group) in entries {
+			if group != current_group {
+				if !lines.is_empty() {
+					lines.push(String::new());
+				}
+				if let Some(header) = &group {
+					lines.push(header.clone().bold().to_string());
+				}
+				current_group = group;
+			}
+			let line = match index {
+				Some(index) => choice.response_line(&labels.label(index + 1, choices.len()), text_context)?,
+				None => choice.locked_line(text_context)?
+			};
+			lines.push(line);
+		}
+		Ok(lines.join("\n"))
+	}
+
+	/// Finds why this choice can never be used, regardless of the player's state.
+	/// 
+	/// Only static requirements are checked: the `version`, note requirements and `once` notes that aren't templated,
+	/// and the parts of a `when` condition joined by `and` that are literals, names, or negated names.
+	/// A requirement that the player has a note contradicts a `when` condition forbidding that name,
+	/// but not the other way around, since a name is also truthy when a variable by that name is set.
+	pub fn contradictions(&self, game_version: &Version) -> Vec<String> {
+		let mut result = Vec::new();
+		if let Some(version) = &self.version {
+			if !version.matches(game_version) {
+				result.push(format!("requires game version '{version}', but the game is version '{game_version}'"));
+			}
+		}
+		// Each name is paired with whether it must be present and whether it is strictly a note
+		let mut constraints: Vec<(&str, bool, bool, String)> = Vec::new();
+		if let Some(actions) = &self.notes {
+			for requirement in actions.require.iter().flatten() {
+				if let (false, Some(has)) = (requirement.name.is_templatable(), requirement.has.as_value()) {
+					let name = requirement.name.content.as_str();
+					let reason = if has { "requires" } else { "forbids" };
+					constraints.push((name, has, true, format!("{reason} note '{name}'")));
+				}
+			}
+			if let Some(once) = actions.once.as_ref().filter(|once| !once.is_templatable()) {
+				constraints.push((&once.content, false, true, format!("is only usable once through note '{}'", once.content)));
+			}
+		}
+		if let Some(when) = &self.when {
+			for condition in when.conjuncts() {
+				let (operand, present) = match condition {
+					Condition::Truthy(operand) => (operand, true),
+					Condition::Not(inner) => match inner.as_ref() {
+						Condition::Truthy(operand) => (operand, false),
+						_ => continue
+					},
+					_ => continue
+				};
+				match operand {
+					Operand::Literal(value) if is_truthy(value) != present => {
+						result.push(format!("has a `when` condition '{when}' that is always false"));
+					},
+					Operand::Variable(name) => {
+						let reason = if present { "requires" } else { "forbids" };
+						constraints.push((name, present, false, format!("`when` condition {reason} '{name}'")));
+					},
+					_ => {}
+				}
+			}
+		}
+		for (index, (name, present, note, reason)) in constraints.iter().enumerate() {
+			for (other_name, other_present, other_note, other_reason) in &constraints[index + 1..] {
+				if name != other_name || present == other_present {
+					continue;
+				}
+				// A forbidden note doesn't rule out a truthy variable of the same name
+				let forbidden_note = if *present { *other_note } else { *note };
+				let required_truthy = if *present { !note } else { !other_note };
+				if !(forbidden_note && required_truthy) {
+					result.push(format!("{reason}, but {other_reason}"));
+				}
+			}
+		}
+		result
 	}
 
 	/// Whether this choice jumps to a specific prompt.
@@ -247,4 +736,57 @@ impl Choice {
 			Some(jump) => jump.matches(file, other_name, other_file)
 		}
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeSet;
+
+	use crate::{core::{manifest::Manifest, resources::Resources}, loading::base::Loader, text::context::TextContext};
+
+	use super::{Choice, Notes, VariableList, Variables};
+
+	#[test]
+	fn list_round_trips() {
+		let list = VariableList(vec!["sword".to_owned(), "e\u{301}pe\u{301}e".to_owned(), "\"quoted\", [bracketed]".to_owned()]);
+		assert_eq!(VariableList::parse(&list.serialize()), Some(list));
+	}
+
+	#[test]
+	fn plain_string_is_not_list() {
+		assert_eq!(VariableList::parse("[Bob]"), None);
+		assert_eq!(VariableList::parse("[\"Bob\"]"), None);
+	}
+
+	#[test]
+	fn locked_choices_stay_in_group() {
+		let config = Manifest::minimal();
+		let resources = Resources::load(&Loader::new(std::env::temp_dir().join("nage-test-empty")), &config).unwrap();
+		let context = TextContext::new(&config, Notes::new(), Variables::new(), BTreeSet::new(), 0, "en_us", &resources);
+		let choice = |text: &str, group: &str| serde_yaml::from_str::<Choice>(&format!("response:\n  text: {text}\ngroup: {group}\njump:\n  prompt: start")).unwrap();
+		let (shop, sell, road, locked) = (choice("Buy", "Shop"), choice("Sell", "Shop"), choice("Leave", "Road"), choice("Steal", "Shop"));
+		let display = Choice::display(&vec![&shop, &sell, &road], &[&locked], &context).unwrap();
+		let lines: Vec<String> = display.lines()
+			.map(|line| String::from_utf8(strip_ansi(line.as_bytes())).unwrap())
+			.collect();
+		assert_eq!(lines.iter().filter(|line| line.as_str() == "Shop").count(), 1);
+		let steal = lines.iter().position(|line| line.contains("Steal")).unwrap();
+		let road = lines.iter().position(|line| line == "Road").unwrap();
+		assert!(steal < road);
+	}
+
+	/// Removes the escape sequences used for styling.
+	fn strip_ansi(bytes: &[u8]) -> Vec<u8> {
+		let mut result = Vec::new();
+		let mut iter = bytes.iter();
+		while let Some(&byte) = iter.next() {
+			if byte == 0x1b {
+				iter.by_ref().find(|&&next| next == b'm');
+			}
+			else {
+				result.push(byte);
+			}
+		}
+		result
+	}
 }
\ No newline at end of file
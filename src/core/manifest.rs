@@ -1,14 +1,14 @@
-use std::{collections::{HashMap, HashSet}, str::FromStr};
+use std::{collections::{HashMap, BTreeSet}, str::FromStr};
 
 use anyhow::{Result, anyhow, Context};
 use semver::{Version, VersionReq};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{loading::base::Loader, text::{display::{TextSpeed, TextLines}, templating::TemplatableValue}, NAGE_VERSION};
+use crate::{loading::base::Loader, text::{display::{TextSpeed, TextLines, Text}, templating::{TemplatableValue, TemplatableString}, conditions::Condition}, NAGE_VERSION};
 
-use super::{choice::{Variables, Notes, SoundAction, SoundActionMode}, player::PathEntry, resources::UnlockedInfoPages};
+use super::{choice::{Variables, VariableList, Notes, SoundAction, SoundActionMode, ChoiceLabels}, player::PathEntry, resources::UnlockedInfoPages, path::Path};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 /// A collection of settings that identify information about the game itself and its authors.
 pub struct Metadata {
@@ -16,7 +16,7 @@ pub struct Metadata {
 	id: Option<String>,
 	pub authors: Vec<String>,
 	pub version: Version,
-	pub contact: Option<Vec<String>>
+	pub contact: Option<Vec<TemplatableString>>
 }
 
 impl Metadata {
@@ -25,7 +25,7 @@ impl Metadata {
 	}
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(default, deny_unknown_fields)]
 pub struct Dependencies {
 	pub nage: VersionReq
@@ -39,31 +39,217 @@ impl Default for Dependencies {
 	}
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(default, deny_unknown_fields)]
 pub struct HistorySettings {
 	pub locked: bool,
-	pub size: usize
+	pub size: usize,
+	/// Whether to keep every history entry, ignoring `size`, so that players can go back to the very start.
+	/// 
+	/// History is stored in save files, so these grow with every choice a player takes.
+	pub unbounded: bool
+}
+
+impl HistorySettings {
+	/// Whether a history of a certain length must drop its oldest entry.
+	pub fn exceeded(&self, length: usize) -> bool {
+		!self.unbounded && length > self.size
+	}
 }
 
 impl Default for HistorySettings {
 	fn default() -> Self {
 		Self { 
 			locked: false,
-			size: 5
+			size: 5,
+			unbounded: false
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalSettings {
+	/// Whether log entries are kept in the player's journal.
+	pub enabled: bool,
+	/// The maximum amount of journal entries, dropping the oldest first. If omitted, the journal is unbounded.
+	/// 
+	/// The journal is stored in save files, so a limit keeps them small in long games.
+	pub size: Option<usize>
+}
+
+impl Default for JournalSettings {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			size: None
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// The input that signals the game to quit.
+pub enum QuitBinding {
+	/// Pressing Ctrl-C or Ctrl-D.
+	#[default]
+	Interrupt,
+	/// Pressing Escape, in addition to the interrupt keys.
+	Esc,
+	/// Entering a word, in addition to the interrupt keys.
+	Word(String)
+}
+
+impl QuitBinding {
+	/// Describes how to signal quitting, such as "Press Esc".
+	pub fn instruction(&self) -> String {
+		use QuitBinding::*;
+		match self {
+			Interrupt => "Press Ctrl-C".to_owned(),
+			Esc => "Press Esc".to_owned(),
+			Word(word) => format!("Enter '{word}'")
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuitSettings {
+	pub binding: QuitBinding,
+	/// Whether quitting must be confirmed, either by answering yes or by giving the quit signal again.
+	pub confirm: bool
+}
+
+impl Default for QuitSettings {
+	fn default() -> Self {
+		Self {
+			binding: QuitBinding::default(),
+			confirm: true
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// The animation shown while text waits for a duration.
+pub enum WaitIndicatorStyle {
+	/// Shows nothing.
+	#[default]
+	None,
+	/// Cycles through up to three dots.
+	Dots,
+	/// Rotates a line.
+	Spinner
+}
+
+impl WaitIndicatorStyle {
+	/// The frames of the animation, displayed in order and repeated.
+	pub fn frames(&self) -> &'static [&'static str] {
+		use WaitIndicatorStyle::*;
+		match self {
+			None => &[],
+			Dots => &["", ".", "..", "..."],
+			Spinner => &["|", "/", "-", "\\"]
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaitIndicatorSettings {
+	pub style: WaitIndicatorStyle,
+	/// The shortest wait, in milliseconds, for which the indicator is shown.
+	pub threshold: u64,
+	/// The milliseconds each animation frame is displayed for.
+	pub interval: u64
+}
+
+impl Default for WaitIndicatorSettings {
+	fn default() -> Self {
+		Self {
+			style: WaitIndicatorStyle::default(),
+			threshold: 1000,
+			interval: 250
 		}
 	}
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+/// The strings displayed when awaiting input. Unset indicators default to `> `.
+pub struct InputIndicatorSettings {
+	/// The indicator displayed when selecting a choice.
+	pub choice: Option<TemplatableString>,
+	/// The indicator displayed when entering a variable whose input has no `text`.
+	pub variable: Option<TemplatableString>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(default, deny_unknown_fields)]
 pub struct Settings {
 	pub save: bool,
 	pub debug: bool,
 	pub speed: TextSpeed,
+	/// Named text speeds that content can reference in place of an inline speed.
+	pub speed_presets: HashMap<String, TextSpeed>,
 	pub history: HistorySettings,
+	pub journal: JournalSettings,
+	/// The maximum size of a serialized save file in bytes. If omitted, save files are unbounded.
+	/// 
+	/// Before writing, the oldest journal entries are dropped first, followed by the oldest history entries.
+	pub max_save_size: Option<usize>,
+	pub quit: QuitSettings,
+	/// Shorthand names for runtime commands, such as `q: quit`. An expansion may include arguments.
+	pub aliases: HashMap<String, String>,
+	/// The language new players start with. If unset, the system language is used when a translation matches it.
 	pub lang: Option<String>,
-	pub channels: Option<HashMap<String, bool>>
+	/// The languages displayed right-to-left. See [`TextMode::format_rtl`](crate::text::display::TextMode::format_rtl).
+	pub rtl_languages: BTreeSet<String>,
+	/// The sound channels mapped to whether they are enabled for new players.
+	/// 
+	/// The [`Settings::CHANNEL_WILDCARD`] key is an alias of `default_enabled` that takes precedence over it.
+	pub channels: Option<HashMap<String, Option<bool>>>,
+	/// Whether sound channels are enabled unless specified otherwise.
+	/// 
+	/// This applies to channels declared without a value, such as `music: ~`, and to channels that aren't declared at all.
+	pub default_enabled: bool,
+	/// Whether to initialize audio at all. If `false`, no output device is opened and sound actions do nothing.
+	pub audio: bool,
+	/// Whether to print all text instantly, ignoring text speeds and waits. Intended for automated runs and testing.
+	/// 
+	/// Unlike the player's `instant` text speed, this also skips waits.
+	pub instant_text: bool,
+	/// Whether to parse prompt files on first access rather than all at startup.
+	pub lazy_prompts: bool,
+	/// Whether to decode every sound file at startup rather than upon first play.
+	/// 
+	/// Preloading avoids stutters when a sound is first played at the cost of keeping every decoded sound in memory.
+	pub preload_sounds: bool,
+	/// Whether to ask for a profile name when the game is first run.
+	pub profiles: bool,
+	/// The prompt to jump to when the current prompt has no usable choices, rather than crashing.
+	/// 
+	/// If the `file` key is omitted, the prompt is looked up in the current prompt's file.
+	pub no_choices_fallback: Option<Path>,
+	/// Whether to display a prompt's choices before its text.
+	pub choices_first: bool,
+	/// How choices are labeled and selected.
+	pub choice_labels: ChoiceLabels,
+	/// Whether scripts have full access to the Lua standard library, including files and processes.
+	/// 
+	/// Scripts are sandboxed by default since games may be shared; this is meant for trusted local development.
+	pub trusted_scripts: bool,
+	/// The most Lua instructions a single script evaluation may execute before it is aborted.
+	/// If omitted, scripts may run indefinitely.
+	pub script_instruction_limit: Option<u64>,
+	/// Whether choices without a `log` key add their response text to the log. Enabled by default;
+	/// disable it to only log choices that declare a `log` entry.
+	pub log_responses: bool,
+	/// The hint displayed while text waits for a keypress.
+	pub wait_hint: TemplatableString,
+	/// The animation displayed while text waits for a duration.
+	pub wait_indicator: WaitIndicatorSettings,
+	pub input_indicators: InputIndicatorSettings
 }
 
 impl Default for Settings {
@@ -72,31 +258,74 @@ impl Default for Settings {
 			save: true,
 			debug: false,
 			speed: TextSpeed::Delay(TemplatableValue::value(5)),
+			speed_presets: HashMap::new(),
 			history: HistorySettings::default(),
+			journal: JournalSettings::default(),
+			max_save_size: None,
+			quit: QuitSettings::default(),
+			aliases: HashMap::new(),
 			lang: None,
-			channels: None
+			rtl_languages: BTreeSet::new(),
+			channels: None,
+			default_enabled: false,
+			audio: true,
+			instant_text: false,
+			lazy_prompts: false,
+			preload_sounds: true,
+			profiles: false,
+			no_choices_fallback: None,
+			choices_first: false,
+			choice_labels: ChoiceLabels::default(),
+			trusted_scripts: false,
+			script_instruction_limit: Some(10_000_000),
+			log_responses: true,
+			wait_hint: TemplatableString::from(String::from("Press any key to continue...")),
+			wait_indicator: WaitIndicatorSettings::default(),
+			input_indicators: InputIndicatorSettings::default()
 		}
 	}
 }
 
 impl Settings {
-	pub fn enabled_channels(&self) -> HashSet<String> {
+	/// The `channels` key setting whether channels are enabled by default rather than declaring a channel.
+	pub const CHANNEL_WILDCARD: &'static str = "*";
+
+	/// Whether channels are enabled unless specified otherwise, according to the wildcard or the `default_enabled` setting.
+	pub fn channels_enabled_by_default(&self) -> bool {
+		self.channels.as_ref()
+			.and_then(|map| map.get(Self::CHANNEL_WILDCARD).copied().flatten())
+			.unwrap_or(self.default_enabled)
+	}
+
+	/// The names of the declared sound channels, excluding the wildcard.
+	pub fn declared_channels(&self) -> Vec<&String> {
+		self.channels.iter()
+			.flat_map(|map| map.keys())
+			.filter(|channel| *channel != Self::CHANNEL_WILDCARD)
+			.collect()
+	}
+
+	/// The declared channels enabled for new players, using the wildcard default for channels without a value.
+	pub fn enabled_channels(&self) -> BTreeSet<String> {
+		let default = self.channels_enabled_by_default();
 		self.channels.as_ref().map(|map| {
 			map.iter()
-    			.filter(|(_, &enabled)| enabled)
+				.filter(|(channel, _)| *channel != Self::CHANNEL_WILDCARD)
+    			.filter(|(_, enabled)| enabled.unwrap_or(default))
 				.map(|(key, _)| key.clone())
 				.collect()
 		})
-		.unwrap_or(HashSet::new())
+		.unwrap_or_default()
 	}
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct EntrypointSoundAction {
-	name: String,
+	pub name: String,
 	channel: String,
 	seek: Option<u64>,
-	speed: Option<f64>
+	speed: Option<f64>,
+	when: Option<Condition>
 }
 
 impl Into<SoundAction> for EntrypointSoundAction {
@@ -106,25 +335,55 @@ impl Into<SoundAction> for EntrypointSoundAction {
 			channel: self.channel.into(), 
 			mode: TemplatableValue::value(SoundActionMode::default()), 
 			seek: self.seek.map(TemplatableValue::value), 
-			speed: self.speed.map(TemplatableValue::value) 
+			speed: self.speed.map(TemplatableValue::value),
+			when: self.when
 		}
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Entrypoint {
 	pub path: PathEntry,
 	pub background: Option<TextLines>,
+	/// A file, relative to the game directory, whose content is displayed literally before any `background` lines.
+	/// 
+	/// This keeps multi-line content such as ASCII art out of the manifest.
+	pub background_file: Option<String>,
 	pub notes: Option<Notes>,
 	pub variables: Option<Variables>,
+	pub lists: Option<HashMap<String, Vec<String>>>,
 	#[serde(rename = "info")]
 	pub info_pages: Option<UnlockedInfoPages>,
 	pub log: Option<Vec<String>>,
 	pub sounds: Option<Vec<EntrypointSoundAction>>
 }
 
-#[derive(Deserialize, Debug)]
+impl Entrypoint {
+	/// Reads the `background_file`, if any, and prepends its content to the `background` lines.
+	fn load_background_file(&mut self, loader: &Loader) -> Result<()> {
+		let Some(file) = &self.background_file else {
+			return Ok(());
+		};
+		let path = loader.get_path(file);
+		let content = std::fs::read_to_string(&path)
+			.with_context(|| format!("Failed to read background file '{}'", path.display()))?;
+		self.background.get_or_insert_with(Vec::new)
+			.insert(0, Text::literal(content.trim_end()));
+		Ok(())
+	}
+
+	/// Combines the entrypoint's `variables` and `lists` into a player's starting [`Variables`].
+	pub fn initial_variables(&self) -> Variables {
+		let mut variables = self.variables.clone().unwrap_or_default();
+		for (name, values) in self.lists.iter().flatten() {
+			variables.insert(name.clone(), VariableList(values.clone()).serialize());
+		}
+		variables
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Manifest {
 	pub metadata: Metadata,
@@ -138,14 +397,15 @@ impl Manifest {
 	pub const FILE: &'static str = "nage.yml";
 
 	pub fn load(loader: &Loader) -> Result<Self> {
-		let config: Self = loader.load_file(Self::FILE)?;
+		let mut config: Self = loader.load_file(Self::FILE)?;
+		config.entry.load_background_file(loader)?;
 		config.validate().with_context(|| "Failed to validate manifest")?;
 		Ok(config)
 	}
 
 	fn validate(&self) -> Result<()> {
-		if self.settings.history.size == 0 {
-			return Err(anyhow!("`settings.history.size` must be non-zero"));
+		if self.settings.history.size == 0 && !self.settings.history.unbounded {
+			return Err(anyhow!("`settings.history.size` must be non-zero; set `settings.history.unbounded` for unlimited history"));
 		}
 		let nage_version = Version::from_str(NAGE_VERSION)?;
 		if !self.dependencies.nage.matches(&nage_version) {
@@ -154,6 +414,27 @@ impl Manifest {
 				self.dependencies.nage, NAGE_VERSION
 			))
 		}
+		for speed in self.entry.background.iter().flatten().filter_map(|text| text.speed.as_ref()) {
+			speed.get(&self.settings).with_context(|| "Text speed in `entry.background` is invalid")?;
+		}
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Manifest {
+	/// A manifest with only the required keys and audio disabled.
+	pub fn minimal() -> Self {
+		let content = "
+metadata:
+  name: Test
+  authors: []
+  version: 0.1.0
+settings:
+  audio: false
+entry:
+  path: main:start
+";
+		serde_yaml::from_str(content).unwrap()
+	}
+}
@@ -1,24 +1,110 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use result::OptionResultExt;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Deserializer, de::{DeserializeOwned, Error as DeError}};
 
 use crate::text::{templating::TemplatableString, context::TextContext};
 
 use super::player::PathEntry;
 
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Serialize, Debug)]
+/// A prompt path that may be templated, written as a `file` and `prompt` map or as a [shorthand](Path::parse_shorthand) string.
 pub struct Path {
 	pub file: Option<TemplatableString>,
 	pub prompt: TemplatableString
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathFields {
+	file: Option<TemplatableString>,
+	prompt: TemplatableString
+}
+
+/// A path as written in content; either a shorthand string or a map of fields `F`.
+pub(crate) enum PathSource<F> {
+	/// The file, if any, and prompt of a shorthand path.
+	Shorthand(Option<String>, String),
+	Fields(F)
+}
+
+impl<F> PathSource<F> where F: DeserializeOwned {
+	/// Deserializes either a shorthand path string, parsed with [`Path::parse_shorthand`], or a map of fields.
+	pub fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		match serde_yaml::Value::deserialize(deserializer)? {
+			serde_yaml::Value::String(shorthand) => {
+				let (file, prompt) = Path::parse_shorthand(&shorthand).map_err(DeError::custom)?;
+				Ok(PathSource::Shorthand(file.map(str::to_owned), prompt.to_owned()))
+			},
+			value => F::deserialize(Self::stringify_fields(value)).map(PathSource::Fields).map_err(DeError::custom)
+		}
+	}
+
+	/// Converts number and boolean field values to strings, since a buffered [`Value`](serde_yaml::Value) can't be read as another type.
+	fn stringify_fields(value: serde_yaml::Value) -> serde_yaml::Value {
+		use serde_yaml::Value::*;
+		match value {
+			Mapping(map) => Mapping(map.into_iter()
+				.map(|(key, value)| {
+					let value = match value {
+						Number(number) => String(number.to_string()),
+						Bool(boolean) => String(boolean.to_string()),
+						value => value
+					};
+					(key, value)
+				})
+				.collect()),
+			value => value
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Path {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		let result = match PathSource::<PathFields>::deserialize(deserializer)? {
+			PathSource::Shorthand(file, prompt) => Path { file: file.map(TemplatableString::from), prompt: prompt.into() },
+			PathSource::Fields(fields) => Path { file: fields.file, prompt: fields.prompt }
+		};
+		Ok(result)
+	}
+}
+
 impl Path {
+	/// The character separating the file and prompt in a shorthand path such as `file/path:prompt`.
+	pub const SEPARATOR: char = ':';
+
+	/// Parses a shorthand path such as `file/path:prompt` into its file, if present, and prompt.
+	/// 
+	/// The path is split at the last separator outside of any `(...)`, `<...>`, or `{{ ... }}` template,
+	/// so that templates such as script function calls can contain the separator.
+	pub fn parse_shorthand(shorthand: &str) -> Result<(Option<&str>, &str)> {
+		let mut depth: usize = 0;
+		let mut split = None;
+		for (index, c) in shorthand.char_indices() {
+			match c {
+				'(' | '<' | '{' => depth += 1,
+				')' | '>' | '}' => depth = depth.saturating_sub(1),
+				Self::SEPARATOR if depth == 0 => split = Some(index),
+				_ => ()
+			}
+		}
+		let (file, prompt) = match split {
+			Some(index) => (Some(&shorthand[..index]), &shorthand[(index + 1)..]),
+			None => (None, shorthand)
+		};
+		if prompt.is_empty() || file.map(str::is_empty).unwrap_or(false) {
+			return Err(anyhow!("Invalid path '{shorthand}'; expected 'prompt' or 'file:prompt'"));
+		}
+		Ok((file, prompt))
+	}
+
 	pub fn is_not_templatable(&self) -> bool {
-		self.prompt.is_templatable()
-			&& self.file.as_ref().map(|t| t.is_templatable()).unwrap_or(false)
+		!self.prompt.is_templatable()
+			&& !self.file.as_ref().map(|t| t.is_templatable()).unwrap_or(false)
 	}
 
+	/// Fills this path into a [`PathEntry`].
+	/// 
+	/// If the `file` key is omitted, the path is relative to the file of the `full` path, usually the current prompt.
 	pub fn fill(&self, full: &PathEntry, text_context: &TextContext) -> Result<PathEntry> {
 		let file = self.file.as_ref().map(|t| t.fill(text_context))
 			.invert()?
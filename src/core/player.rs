@@ -1,14 +1,14 @@
-use std::{collections::{HashMap, HashSet, VecDeque}, vec};
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, vec, time::{Instant, Duration}};
 
-use anyhow::{Result, anyhow};
-use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow, Context};
+use serde::{Serialize, Deserialize, Deserializer, de::Error as DeError};
 use unicode_truncate::UnicodeTruncateStr;
 
-use crate::{game::input::VariableInputResult, text::{templating::TemplatableString, context::TextContext}};
+use crate::{game::input::VariableInputResult, text::{templating::TemplatableString, context::TextContext, display::PlayerTextSpeed}};
 
-use super::{choice::{NoteApplication, Notes, Variables, Choice, VariableApplications}, manifest::Manifest, resources::{UnlockedInfoPages, Resources}, prompt::PromptModel};
+use super::{choice::{NoteApplication, Notes, Variables, Choice, VariableApplications}, manifest::Manifest, resources::{UnlockedInfoPages, Resources}, prompt::{PromptModel, Prompt}, path::PathSource};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 /// A single variable value recording.
 pub struct VariableEntry {
 	/// The new variable value.
@@ -17,8 +17,8 @@ pub struct VariableEntry {
 	pub previous: Option<String>
 }
 
-/// A map of variable names to value recordings.
-pub type VariableEntries = HashMap<String, VariableEntry>;
+/// A sorted map of variable names to value recordings.
+pub type VariableEntries = BTreeMap<String, VariableEntry>;
 
 impl VariableEntry {
 	pub fn new(name: &String, value: String, variables: &Variables) -> Self {
@@ -42,7 +42,7 @@ impl VariableEntry {
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct NoteEntry {
 	pub value: String,
 	pub take: bool
@@ -64,13 +64,31 @@ impl NoteEntry {
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
+/// A filled prompt path, written as a `file` and `prompt` map or as a `file:prompt` shorthand string.
 pub struct PathEntry {
 	pub file: String,
 	pub prompt: String
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathEntryFields {
+	file: String,
+	prompt: String
+}
+
+impl<'de> Deserialize<'de> for PathEntry {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		match PathSource::<PathEntryFields>::deserialize(deserializer)? {
+			PathSource::Shorthand(Some(file), prompt) => Ok(PathEntry { file, prompt }),
+			PathSource::Shorthand(None, prompt) => Err(DeError::custom(format!("Path '{prompt}' must specify a file, such as 'file:{prompt}'"))),
+			PathSource::Fields(fields) => Ok(PathEntry { file: fields.file, prompt: fields.prompt })
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 /// A reversible recording of a prompt jump.
 pub struct HistoryEntry {
 	/// The prompt path the player jumped to.
@@ -104,6 +122,22 @@ impl HistoryEntry {
 	}
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A log entry kept in the player's journal.
+pub struct LogEntry {
+	/// The filled log text.
+	pub text: String,
+	/// The seconds the player had spent playing when the entry was gained.
+	pub playtime: u64
+}
+
+impl LogEntry {
+	/// Formats the entry's play time as `hours:minutes:seconds`.
+	pub fn timestamp(&self) -> String {
+		format!("{}:{:02}:{:02}", self.playtime / 3600, self.playtime / 60 % 60, self.playtime % 60)
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// A player data tracker.
 pub struct Player {
@@ -112,7 +146,10 @@ pub struct Player {
 	/// The player's display language.
 	pub lang: String,
 	/// The player's enabled sound channels.
-	pub channels: HashSet<String>,
+	pub channels: BTreeSet<String>,
+	#[serde(default)]
+	/// The player's preferred text speed.
+	pub speed: PlayerTextSpeed,
 	/// The player's current notes.
 	pub notes: Notes,
 	/// The player's current variables.
@@ -121,8 +158,43 @@ pub struct Player {
 	pub info_pages: UnlockedInfoPages,
 	/// The player's current log entries.
 	pub log: Vec<String>,
+	#[serde(default)]
+	/// Every log entry the player has gained, including those reversed by going back.
+	/// 
+	/// Unlike `log`, this is not tied to the history and is only bounded by the `journal` settings.
+	pub journal: Vec<LogEntry>,
 	/// Recordings of each prompt jump and their associated value changes.
-	pub history: VecDeque<HistoryEntry>
+	pub history: VecDeque<HistoryEntry>,
+	#[serde(default)]
+	/// The seconds spent playing, as of the last [`Player::record_playtime`] call.
+	/// 
+	/// Play time only advances while the game is running and is persisted in save files.
+	pub playtime: u64,
+	#[serde(skip)]
+	/// When the uncounted portion of the current session began.
+	session: Option<Instant>,
+	#[serde(skip)]
+	/// Whether the latest history entry has changed since its prompt was last [entered](Player::enter).
+	/// 
+	/// This is unset for loaded saves so that resuming doesn't enter the prompt again.
+	entering: bool
+}
+
+/// Compares the data persisted in saves, ignoring the state of the current session.
+impl PartialEq for Player {
+	fn eq(&self, other: &Self) -> bool {
+		self.began == other.began
+			&& self.lang == other.lang
+			&& self.channels == other.channels
+			&& self.speed == other.speed
+			&& self.notes == other.notes
+			&& self.variables == other.variables
+			&& self.info_pages == other.info_pages
+			&& self.log == other.log
+			&& self.journal == other.journal
+			&& self.history == other.history
+			&& self.playtime == other.playtime
+	}
 }
 
 impl Player {
@@ -133,19 +205,38 @@ impl Player {
 			began: false,
 			lang: config.settings.lang.clone().unwrap_or(String::from("en_us")),
 			channels: config.settings.enabled_channels(),
-			notes: config.entry.notes.clone().unwrap_or(HashSet::new()),
-			variables: config.entry.variables.clone().unwrap_or(HashMap::new()),
-			info_pages: config.entry.info_pages.clone().unwrap_or(HashSet::new()),
+			speed: PlayerTextSpeed::default(),
+			notes: config.entry.notes.clone().unwrap_or_default(),
+			variables: config.entry.initial_variables(),
+			info_pages: config.entry.info_pages.clone().unwrap_or_default(),
 			log: config.entry.log.clone().unwrap_or(Vec::new()),
-			history: VecDeque::from(vec![entry])
+			journal: Vec::new(),
+			history: VecDeque::from(vec![entry]),
+			playtime: 0,
+			session: None,
+			entering: true
 		}
 	}
 
+	/// The total seconds spent playing, including the current session.
+	pub fn playtime(&self) -> u64 {
+		self.playtime + self.session.map(|start| start.elapsed().as_secs()).unwrap_or(0)
+	}
+
+	/// Adds the whole seconds played since the last call to the recorded play time, starting the session if necessary.
+	pub fn record_playtime(&mut self) {
+		let now = Instant::now();
+		let start = self.session.unwrap_or(now);
+		let elapsed = now.duration_since(start).as_secs();
+		self.playtime += elapsed;
+		self.session = Some(start + Duration::from_secs(elapsed));
+	}
+
 	/// Accepts a single [`NoteApplication`].
 	/// 
 	/// If `take` is `true`, attempts to remove the note.
 	/// Otherwise, inserts the note if not already present.
-	fn apply_note(&mut self, name: &str, take: bool, reverse: bool) -> Result<()> {
+	pub fn apply_note(&mut self, name: &str, take: bool, reverse: bool) -> Result<()> {
 		let take = if reverse { !take } else { take };
 		if take {
 			self.notes.remove(name);
@@ -194,6 +285,7 @@ impl Player {
 				break;
 			}
 		}
+		self.entering = true;
 		Ok(())
 	}
 
@@ -228,39 +320,127 @@ impl Player {
 		Ok(())
 	}
 
-	pub fn choose(&mut self, choice: &Choice, input: Option<&VariableInputResult>, config: &Manifest, model: &PromptModel, resources: &Resources, text_context: &TextContext) -> Result<()> {
+	/// Pushes a new history entry, dropping the oldest entry if the history exceeds its configured size.
+	fn push_entry(&mut self, entry: HistoryEntry, config: &Manifest) {
+		self.history.push_back(entry);
+		if config.settings.history.exceeded(self.history.len()) {
+			self.history.pop_front();
+		}
+		self.entering = true;
+	}
+
+	/// Jumps to a prompt without choosing a choice, such as to the `no_choices_fallback` prompt.
+	/// 
+	/// The jump is recorded as a redirect, so going back also reverses the jump before it.
+	pub fn jump(&mut self, path: &PathEntry, config: &Manifest) {
+		let entry = HistoryEntry { redirect: true, ..HistoryEntry::new(path) };
+		self.push_entry(entry, config);
+	}
+
+	/// Creates a [`TextContext`] from a snapshot of the player's current data and preferences.
+	pub fn text_context<'a>(&self, config: &'a Manifest, resources: &'a Resources) -> TextContext<'a> {
+		let mut context = TextContext::new(config, self.notes.clone(), self.variables.clone(), self.channels.clone(), self.playtime(), &self.lang, resources);
+		context.speed = self.speed;
+		context.info_pages = self.info_pages.clone();
+		context.file = self.latest_entry().ok().map(|entry| entry.path.file.clone());
+		context
+	}
+
+	/// Runs a prompt's `on_enter` script, if any, writing its changes to notes and variables back to the player.
+	/// 
+	/// The script only runs once per history entry, so redisplaying the same prompt doesn't run it again.
+	pub fn enter(&mut self, prompt: &Prompt, config: &Manifest, resources: &Resources) -> Result<()> {
+		if !std::mem::take(&mut self.entering) {
+			return Ok(());
+		}
+		if let Some(script) = &prompt.on_enter {
+			let (notes, variables) = resources.scripts.run_hook(script, &self.text_context(config, resources))?;
+			self.notes = notes;
+			self.variables = variables;
+		}
+		Ok(())
+	}
+
+	/// Applies a choice's effects to the player's state without any output, in the following order:
+	/// 1. The `on_select` script, if any, whose changes to notes and variables are written back immediately
+	/// 2. The history entry, including the jump, `notes` actions, `variables` and `lists` changes, and `info` unlocks
+	/// 
+	/// The history entry is created after the script has run, so going back doesn't reverse the script's changes.
+	/// If the script errors, the choice is aborted before anything is applied.
+	fn apply_choice(&mut self, choice: &Choice, inputs: &[VariableInputResult], config: &Manifest, model: &PromptModel, resources: &Resources, text_context: &TextContext) -> Result<()> {
+		let hooked_context;
+		let text_context = match &choice.on_select {
+			None => text_context,
+			Some(script) => {
+				let (notes, variables) = resources.scripts.run_hook(script, text_context)
+					.with_context(|| format!("Failed to run `on_select` script '{script}'; the choice was not taken"))?;
+				self.notes = notes;
+				self.variables = variables;
+				hooked_context = self.text_context(config, resources);
+				&hooked_context
+			}
+		};
 		let latest = self.latest_entry()?;
-		if let Some(result) = choice.to_history_entry(&latest, input, config, &self.variables, model, text_context) {
+		if let Some(result) = choice.to_history_entry(&latest, inputs, model, text_context) {
 			let entry = result?;
 			self.apply_entry(&entry, choice, text_context)?;
-			self.history.push_back(entry);
-			if self.history.len() > config.settings.history.size {
-				self.history.pop_front();
-			}
+			self.push_entry(entry, config);
 		}
+		Ok(())
+	}
+
+	/// Plays a choice's `sounds` actions, if any.
+	fn play_choice_sounds(&self, choice: &Choice, resources: &Resources, text_context: &TextContext) -> Result<()> {
 		if let Some(audio) = &resources.audio {
 			if let Some(sounds) = &choice.sounds {
 				for sound in sounds {
-					audio.accept(&self, sound, text_context)?;
+					audio.accept(&self.channels, sound, text_context)?;
 				}
 			}
 		}
 		Ok(())
 	}
 
+	/// Takes a choice using [`Player::apply_choice`] and then plays its `sounds` actions.
+	/// 
+	/// `log` entries are pushed separately by [`Player::try_push_log`].
+	pub fn choose(&mut self, choice: &Choice, inputs: &[VariableInputResult], config: &Manifest, model: &PromptModel, resources: &Resources, text_context: &TextContext) -> Result<()> {
+		self.apply_choice(choice, inputs, config, model, resources, text_context)?;
+		self.play_choice_sounds(choice, resources, text_context)
+	}
+
+	/// Takes a choice and pushes its `log` entry without any output or sounds, returning the path of the resulting prompt.
+	/// 
+	/// This separates the state transition from rendering for embedders and headless drivers.
+	/// If the choice doesn't jump, such as when it's an `ending`, the current path is returned.
+	pub fn transition(&mut self, choice: &Choice, inputs: &[VariableInputResult], config: &Manifest, model: &PromptModel, resources: &Resources, text_context: &TextContext) -> Result<&PathEntry> {
+		self.apply_choice(choice, inputs, config, model, resources, text_context)?;
+		self.try_push_log(choice, config, resources)?;
+		Ok(&self.latest_entry()?.path)
+	}
+
 	pub fn try_push_log(&mut self, choice: &Choice, config: &Manifest, resources: &Resources) -> Result<()> {
-		if let Some(log) = &choice.log {
+		if let Some(log) = choice.log_entry(config) {
 			// Create a new text context using the new variable and note values for the logs
 			// Log page names are not stored in history entries, just whether they were given, so we can fill the name here
-			let new_text_context = TextContext::new(config, self.notes.clone(), self.variables.clone(), &self.lang, resources);
-			self.log.push(log.fill(&new_text_context)?);
+			let new_text_context = self.text_context(config, resources);
+			let text = log.fill(&new_text_context)?;
+			if config.settings.journal.enabled {
+				self.journal.push(LogEntry { text: text.clone(), playtime: self.playtime() });
+				if let Some(size) = config.settings.journal.size {
+					let excess = self.journal.len().saturating_sub(size);
+					self.journal.drain(..excess);
+				}
+			}
+			self.log.push(text);
 		}
 		Ok(())
 	}
 
-	pub fn choose_full(&mut self, choice: &Choice, input: Option<&VariableInputResult>, config: &Manifest, resources: &Resources, model: &PromptModel, text_context: &TextContext) -> Result<()> {
-		self.choose(choice, input, config, model, resources, text_context)?;
-		self.try_push_log(choice, config, resources)
+	/// Takes a choice using [`Player::transition`] and then plays its `sounds` actions.
+	pub fn choose_full(&mut self, choice: &Choice, inputs: &[VariableInputResult], config: &Manifest, resources: &Resources, model: &PromptModel, text_context: &TextContext) -> Result<()> {
+		self.transition(choice, inputs, config, model, resources, text_context)?;
+		self.play_choice_sounds(choice, resources, text_context)
 	}
 
 	/// Returns the player's log entries split into readable chunks of five entries maximum.
@@ -268,6 +448,14 @@ impl Player {
 		self.log.chunks(5).collect()
 	}
 
+	/// Renders the player's journal as a Markdown list of entries prefixed by their timestamps.
+	pub fn journal_text(&self) -> String {
+		self.journal.iter()
+			.map(|entry| format!("* `{}` {}", entry.timestamp(), entry.text))
+			.collect::<Vec<String>>()
+			.join("\n")
+	}
+
 	/// Gets the "front" of each page in a collection of [`Player::log_pages`]; that is, the first entry
 	/// in each page truncated to a readable length.
 	pub fn log_page_fronts(pages: &Vec<&[String]>) -> Vec<String> {
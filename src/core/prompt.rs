@@ -1,11 +1,13 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::{HashMap, HashSet, BTreeMap, BTreeSet}, fmt::Display, cell::OnceCell, path::PathBuf, io::Write};
 
 use anyhow::{Result, Context, anyhow};
+use result::OptionResultExt;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
-use crate::{loading::base::{ContentFile, Contents}, text::{context::TextContext, display::{Text, TextLines}, templating::TemplatableString}};
+use crate::{loading::base::{ContentFile, Loader}, text::{context::TextContext, display::{Text, TextLines}, templating::TemplatableString}};
 
-use super::{choice::{Choices, Choice, Notes}, player::PathEntry};
+use super::{choice::{Choices, Choice, SoundAction, VariableInput}, player::PathEntry, manifest::Manifest, scripts::Scripts};
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -15,14 +17,39 @@ use super::{choice::{Choices, Choice, Notes}, player::PathEntry};
 pub struct Prompt {
 	#[serde(rename = "prompt")]
 	pub text: Option<TextLines>,
-	pub choices: Choices
+	pub choices: Choices,
+	/// How usable choices are ordered before being displayed. Definition order is kept if absent.
+	pub sort: Option<ChoiceSort>,
+	/// A script run whenever the prompt is entered, before its text is printed.
+	/// 
+	/// Changes the script makes to the `notes` and `variables` globals are applied to the player,
+	/// but aren't recorded in the history and thus aren't reversed by going back.
+	pub on_enter: Option<String>,
+	/// The game versions this prompt is meant for, such as `>=1.2`.
+	/// Choices jumping to this prompt are unusable in other versions. See also [`Prompt::version_mismatches`].
+	pub version: Option<VersionReq>,
+	/// Music started upon entering the prompt and stopped upon leaving it, unless the next prompt declares the same track.
+	pub music: Option<SoundAction>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// An ordering applied to a prompt's usable choices.
+/// 
+/// Sorting is stable, so choices that compare equally keep their definition order.
+pub enum ChoiceSort {
+	/// Orders choices alphabetically by their filled response text.
+	Alphabetical,
+	/// Orders choices by their `priority`, highest first.
+	Priority
 }
 
 #[derive(Debug)]
 /// A prompt's overarching function based on its choices.
 pub enum PromptModel<'a> {
-	/// Has one choice. This choice has an `input` field.
-	Input(String, Option<&'a TemplatableString>),
+	/// Has one choice. This choice has an `input` or `inputs` field, whose variable inputs are taken in order.
+	/// Each input is paired with its filled variable name.
+	Input(Vec<(String, &'a VariableInput)>),
 	/// A normal prompt-choice container model.
 	Response,
 	/// Has one choice. This choice lacks response or input; immediately jumps to another prompt.
@@ -44,7 +71,10 @@ impl<'a> PromptModel<'a> {
 	pub fn description(&self) -> String {
 		use PromptModel::*;
 		match self {
-			Input(name, _) => format!("Input; takes user input for the variable '{name}'"),
+			Input(fields) => {
+				let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+				format!("Input; takes user input for the variables '{}'", names.join("', '"))
+			},
 			Response => "Response; standard prompt-choice model".to_owned(),
 			Redirect(_) => "Redirect; jumps to another prompt without input".to_owned(),
 			Ending(_) => "Ending; the game is forced to end".to_owned()
@@ -53,13 +83,68 @@ impl<'a> PromptModel<'a> {
 }
 
 pub type PromptFile = ContentFile<Prompt>;
-pub type Prompts = Contents<Prompt>;
+
+/// A [`PromptFile`] that is parsed either upon loading or upon first access.
+pub struct LazyPromptFile {
+	path: PathBuf,
+	content: OnceCell<PromptFile>
+}
+
+impl LazyPromptFile {
+	/// Returns the parsed prompt file, parsing and caching it first if necessary.
+	pub fn get(&self) -> Result<&PromptFile> {
+		if let Some(content) = self.content.get() {
+			return Ok(content);
+		}
+		let content = Loader::load(&self.path)?;
+		Ok(self.content.get_or_init(|| content))
+	}
+}
+
+/// An ordered map of file names to prompt files.
+/// 
+/// If loaded lazily, each file is only parsed when first accessed, so normal play only parses the files a player visits.
+pub struct Prompts {
+	files: BTreeMap<String, LazyPromptFile>
+}
+
+impl Prompts {
+	/// Walks the `prompts` directory. Unless `lazy` is `true`, every file is also parsed immediately.
+	pub fn load(loader: &Loader, lazy: bool) -> Result<Self> {
+		let files = loader.map_content("prompts", |path| {
+			let file = LazyPromptFile { path, content: OnceCell::new() };
+			if !lazy {
+				file.get()?;
+			}
+			Ok(file)
+		})?;
+		Ok(Self { files })
+	}
+
+	/// Returns the names of all prompt files without parsing them.
+	pub fn keys(&self) -> impl Iterator<Item = &String> {
+		self.files.keys()
+	}
+
+	/// Retrieves and, if necessary, parses a single prompt file.
+	pub fn get(&self, file: &str) -> Option<Result<&PromptFile>> {
+		self.files.get(file).map(LazyPromptFile::get)
+	}
+
+	/// Parses every prompt file that hasn't been parsed yet and returns them all in order.
+	pub fn iter(&self) -> Result<Vec<(&String, &PromptFile)>> {
+		self.files.iter()
+			.map(|(name, file)| Ok((name, file.get()?)))
+			.collect()
+	}
+}
 
 impl Prompt {
 	/// Finds a specific prompt file within a [`Prompts`] object.
 	pub fn get_file<'a>(prompts: &'a Prompts, file: &str) -> Result<&'a PromptFile> {
 		prompts.get(file)
 			.ok_or(anyhow!("Invalid prompt file '{file}'"))
+			.flatten()
 	}
 
 	/// Finds a specific prompt within a [`Prompts`] object.
@@ -71,6 +156,15 @@ impl Prompt {
 			.flatten()
 	}
 
+	/// Returns the names of the files that contain a prompt with a specific name.
+	pub fn files_containing<'a>(prompts: &'a Prompts, name: &str) -> Result<Vec<&'a String>> {
+		let result = prompts.iter()?.into_iter()
+			.filter(|(_, prompt_file)| prompt_file.contains_key(name))
+			.map(|(file, _)| file)
+			.collect();
+		Ok(result)
+	}
+
 	/// Uses [`Prompt::get`] to find a specific prompt.
 	/// 
 	/// The input path **must** have a `file` key.
@@ -78,23 +172,194 @@ impl Prompt {
 		Self::get(prompts, &path.prompt, &path.file)
 	}
 
-	/// Validates this prompt's choices using [`Choice::validate`].
-	pub fn validate(&self, name: &String, file: &String, prompts: &Prompts) -> Result<()> {
+	/// Collects the sound actions of every prompt's text and choices.
+	pub fn sound_actions(prompts: &Prompts) -> Result<Vec<&SoundAction>> {
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| {
+				prompt.text.iter().flatten()
+					.filter_map(|text| text.sound.as_ref())
+					.chain(prompt.music.iter())
+					.chain(prompt.choices.iter().flat_map(|choice| choice.sound_actions()))
+			})
+			.collect();
+		Ok(result)
+	}
+
+	/// Collects the names of the images displayed before every prompt's text and its choices' responses and endings.
+	pub fn image_names(prompts: &Prompts) -> Result<Vec<&str>> {
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| {
+				prompt.text.iter().flatten()
+					.chain(prompt.choices.iter().flat_map(|choice| choice.response.iter().chain(choice.ending.iter().flatten())))
+			})
+			.filter_map(|text| text.image.as_ref())
+			.map(|image| image.name.as_str())
+			.collect();
+		Ok(result)
+	}
+
+	/// Collects the non-templated names of the info pages required by every prompt's choices.
+	pub fn required_info_pages(prompts: &Prompts) -> Result<BTreeSet<&str>> {
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| prompt.choices.iter().flat_map(|choice| choice.info_required.iter().flatten()))
+			.filter(|page| !page.is_templatable())
+			.map(|page| page.content.as_str())
+			.collect();
+		Ok(result)
+	}
+
+	/// Collects the names of the scripts referenced by every prompt, both as hooks and within displayed strings.
+	pub fn referenced_scripts(prompts: &Prompts) -> Result<BTreeSet<String>> {
+		let components = Self::displayed_strings(prompts)?.into_iter()
+			.flat_map(TemplatableString::script_components);
+		let hooks = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| prompt.on_enter.iter().chain(prompt.choices.iter().flat_map(|choice| choice.on_select.iter())))
+			.cloned();
+		Ok(components.chain(hooks).collect())
+	}
+
+	/// Validates this prompt's `on_enter` script and its choices using [`Choice::validate`].
+	pub fn validate(&self, name: &String, file: &String, prompts: &Prompts, scripts: &Scripts, variables: &HashSet<&str>, config: &Manifest) -> Result<()> {
+		let texts = self.text.iter().flatten()
+			.chain(self.choices.iter().flat_map(|choice| choice.response.iter().chain(choice.ending.iter().flatten())));
+		for speed in texts.filter_map(|text| text.speed.as_ref()) {
+			speed.get(&config.settings)
+				.with_context(|| format!("Text speed in prompt '{name}' in file '{file}' is invalid"))?;
+		}
+		if let Some(script) = &self.on_enter {
+			if !scripts.contains(script) {
+				return Err(anyhow!("`on_enter` of prompt '{name}' in file '{file}' references invalid script '{script}'"));
+			}
+		}
+		if self.music.as_ref().map(|music| music.name.is_none()).unwrap_or(false) {
+			return Err(anyhow!("`music` of prompt '{name}' in file '{file}' must name a sound"));
+		}
 		let has_company = self.choices.len() > 1;
 		// Validate all independent choices
 		self.choices.iter().enumerate()
 			.map(|(index, choice)| {
-				choice.validate(file, has_company, prompts)
+				choice.validate(file, has_company, prompts, scripts, variables)
 					.with_context(|| format!("Error when validating choice #{} of prompt '{name}' in file '{file}'", index + 1))
 			})
 			.collect()
 	}
 
+	/// Collects the names of all variables that are set by the entrypoint or by any choice.
+	pub fn declared_variables<'a>(prompts: &'a Prompts, config: &'a Manifest) -> Result<HashSet<&'a str>> {
+		let entry = &config.entry;
+		let entry_variables = entry.variables.iter().flat_map(|vars| vars.keys())
+			.chain(entry.lists.iter().flat_map(|lists| lists.keys()))
+			.map(|name| name.as_str());
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| prompt.choices.iter())
+			.flat_map(|choice| choice.declared_variables())
+			.chain(entry_variables)
+			.collect();
+		Ok(result)
+	}
+
+	/// Collects the raw content of every string displayed by any prompt or choice.
+	pub fn displayed_strings(prompts: &Prompts) -> Result<BTreeSet<&str>> {
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(_, prompt_file)| prompt_file.values())
+			.flat_map(|prompt| {
+				prompt.text.iter().flatten()
+					.map(|text| text.content.content.as_str())
+					.chain(prompt.choices.iter().flat_map(|choice| choice.displayed_strings()))
+			})
+			.collect();
+		Ok(result)
+	}
+
+	/// Finds the variables referenced in displayed strings that aren't set by the entrypoint or any choice,
+	/// returning each one along with the file and name of the prompt referencing it.
+	/// 
+	/// Global `nage:` variables are ignored, and list accesses such as `items[0]` and `items.length` are checked by their list name.
+	/// Names resolved by scripts or other templates can't be known ahead of time and are skipped.
+	pub fn unknown_variables<'a>(prompts: &'a Prompts, config: &'a Manifest) -> Result<Vec<(&'a String, &'a String, String)>> {
+		let declared = Self::declared_variables(prompts, config)?;
+		let mut result = Vec::new();
+		for (file_name, prompt_file) in prompts.iter()? {
+			for (name, prompt) in prompt_file {
+				let strings = prompt.text.iter().flatten()
+					.map(|text| text.content.content.as_str())
+					.chain(prompt.choices.iter().flat_map(|choice| choice.displayed_strings()));
+				let unknown: BTreeSet<String> = strings
+					.flat_map(TemplatableString::variable_components)
+					.filter(|var| !var.to_lowercase().starts_with("nage:"))
+					.filter(|var| {
+						let list = var.strip_suffix(".length")
+							.or_else(|| var.split_once('[').map(|(list, _)| list))
+							.unwrap_or(var);
+						!declared.contains(list.trim())
+					})
+					.collect();
+				result.extend(unknown.into_iter().map(|var| (file_name, name, var)));
+			}
+		}
+		Ok(result)
+	}
+
+	/// Finds this prompt's choices that can never be used or selected, returning their indices along with the issues.
+	/// 
+	/// See [`Choice::contradictions`] for the checks on each choice.
+	/// Choices that share a keyword are also reported, since only the first usable one can be selected by it.
+	pub fn lint(&self, game_version: &Version) -> Vec<(usize, String)> {
+		let mut result = Vec::new();
+		let mut keywords: HashMap<String, usize> = HashMap::new();
+		for (index, choice) in self.choices.iter().enumerate() {
+			result.extend(choice.contradictions(game_version).into_iter().map(|reason| (index, format!("can never be used; it {reason}"))));
+			if let Some(keyword) = choice.keyword.as_ref().filter(|keyword| !keyword.is_templatable()) {
+				let keyword = keyword.content.trim().to_lowercase();
+				match keywords.get(&keyword) {
+					Some(first) => result.push((index, format!("can't be selected by keyword '{keyword}', since choice #{} uses it first", first + 1))),
+					None => { keywords.insert(keyword, index); }
+				}
+			}
+		}
+		result
+	}
+
+	/// Lints all prompts in a [`Prompts`] map using [`Prompt::lint`],
+	/// returning each issue along with the file and name of the prompt and the 1-based choice number.
+	pub fn lint_all<'a>(prompts: &'a Prompts, config: &Manifest) -> Result<Vec<(&'a String, &'a String, usize, String)>> {
+		let mut result = Vec::new();
+		for (file_name, prompt_file) in prompts.iter()? {
+			for (name, prompt) in prompt_file {
+				result.extend(prompt.lint(&config.metadata.version).into_iter().map(|(index, issue)| (file_name, name, index + 1, issue)));
+			}
+		}
+		Ok(result)
+	}
+
+	/// Finds the prompts whose `version` doesn't match the game's version,
+	/// returning each one's file and name along with its version requirement.
+	/// 
+	/// Such prompts are reported as warnings rather than errors, since content meant for other versions can be merged ahead of time.
+	pub fn version_mismatches<'a>(prompts: &'a Prompts, config: &Manifest) -> Result<Vec<(&'a String, &'a String, &'a VersionReq)>> {
+		let game_version = &config.metadata.version;
+		let result = prompts.iter()?.into_iter()
+			.flat_map(|(file_name, prompt_file)| prompt_file.iter().map(move |(name, prompt)| (file_name, name, prompt)))
+			.filter_map(|(file_name, name, prompt)| {
+				prompt.version.as_ref()
+					.filter(|version| !version.matches(game_version))
+					.map(|version| (file_name, name, version))
+			})
+			.collect();
+		Ok(result)
+	}
+
 	/// Validates all prompts in a [`Prompts`] map.
-	pub fn validate_all(prompts: &Prompts) -> Result<()> {
-		for (file_name, prompt_file) in prompts {
+	pub fn validate_all(prompts: &Prompts, scripts: &Scripts, config: &Manifest) -> Result<()> {
+		let variables = Self::declared_variables(prompts, config)?;
+		for (file_name, prompt_file) in prompts.iter()? {
 			for (name, prompt) in prompt_file {
-				let _ = prompt.validate(name, file_name, prompts)?;
+				prompt.validate(name, file_name, prompts, scripts, &variables, config)?;
 			}
 		}
 		Ok(())
@@ -105,8 +370,12 @@ impl Prompt {
 		use PromptModel::*;
 		if self.choices.len() == 1 {
 			let choice = &self.choices[0];
-			if let Some(input) = &choice.input {
-				return Ok(Input(input.name.fill(text_context)?, input.text.as_ref()));
+			let fields = choice.input_fields();
+			if !fields.is_empty() {
+				let fields = fields.into_iter()
+					.map(|input| Ok((input.name.fill(text_context)?, input)))
+					.collect::<Result<Vec<_>>>()?;
+				return Ok(Input(fields));
 			}
 			else if choice.response.is_none() {
 				if let Some(ending) = &choice.ending {
@@ -118,28 +387,115 @@ impl Prompt {
 		Ok(Response)
 	}
 
-	/// Gathers all choices that a player can use based on the note context.
-	pub fn usable_choices(&self, notes: &Notes, text_context: &TextContext) -> Result<Vec<&Choice>> {
+	/// Gathers all choices that a player can use based on the note context, except `hidden` ones.
+	/// 
+	/// The choices are ordered as they will be displayed and numbered, according to this prompt's `sort` key.
+	pub fn usable_choices(&self, text_context: &TextContext) -> Result<Vec<&Choice>> {
 		let mut result = Vec::new();
-		for choice in &self.choices {
-			if choice.can_player_use(notes, text_context)? {
+		for choice in self.choices.iter().filter(|choice| !choice.hidden) {
+			if choice.can_player_use(text_context)? {
+				result.push(choice);
+			}
+		}
+		match self.sort {
+			None => {},
+			Some(ChoiceSort::Priority) => result.sort_by_key(|choice| std::cmp::Reverse(choice.priority)),
+			Some(ChoiceSort::Alphabetical) => {
+				let mut keyed = result.into_iter()
+					.map(|choice| Ok((choice.response.as_ref().map(|text| text.content.fill(text_context)).invert()?, choice)))
+					.collect::<Result<Vec<_>>>()?;
+				keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+				result = keyed.into_iter().map(|(_, choice)| choice).collect();
+			}
+		}
+		Ok(result)
+	}
+
+	/// Gathers the choices that the player can't use but are displayed anyway according to their `show_locked` key.
+	pub fn locked_choices(&self, text_context: &TextContext) -> Result<Vec<&Choice>> {
+		let mut result = Vec::new();
+		for choice in self.choices.iter().filter(|choice| choice.show_locked && !choice.hidden) {
+			if !choice.can_player_use(text_context)? {
 				result.push(choice);
 			}
 		}
 		Ok(result)
 	}
 
+	/// Gathers the usable choices that can be selected by typing their `keyword`, including `hidden` ones.
+	/// 
+	/// Each choice is paired with its filled, lowercase keyword.
+	pub fn keyword_choices(&self, text_context: &TextContext) -> Result<Vec<(String, &Choice)>> {
+		let mut result = Vec::new();
+		for choice in &self.choices {
+			if let Some(keyword) = &choice.keyword {
+				if choice.can_player_use(text_context)? {
+					result.push((keyword.fill(text_context)?.trim().to_lowercase(), choice));
+				}
+			}
+		}
+		Ok(result)
+	}
+
 	/// Prints the prompt text, if any, and the choices display, if any are responses.
+	/// 
+	/// The text is printed first unless the `choices_first` setting is enabled.
 	pub fn print(&self, model: &PromptModel, display: bool, usable_choices: &Vec<&Choice>, text_context: &TextContext) -> Result<()> {
+		let locked_choices = self.locked_choices(text_context)?;
+		let choices_first = text_context.config.settings.choices_first;
+		if choices_first {
+			Self::print_choices(model, usable_choices, &locked_choices, text_context)?;
+		}
 		if display {
 			if let Some(lines) = &self.text {
 				Text::print_lines_nl(lines, text_context)?;
 			}
 		}
-		let result = if let PromptModel::Response = model {
-			println!("{}\n", Choice::display(usable_choices, text_context)?);
+		if !choices_first {
+			Self::print_choices(model, usable_choices, &locked_choices, text_context)?;
+		}
+		Ok(())
+	}
+
+	/// Renders the prompt text, if any, to a [`String`] without printing it, such as for previews.
+	/// 
+	/// See [`Text::write_lines`] for how text is rendered. Returns an empty string if the prompt has no text.
+	pub fn render_text(&self, text_context: &TextContext) -> Result<String> {
+		let mut out = Vec::new();
+		if let Some(lines) = &self.text {
+			Text::write_lines(lines, text_context, &mut out)?;
+		}
+		let result = String::from_utf8(out)?;
+		Ok(result.trim_end_matches('\n').to_owned())
+	}
+
+	/// Writes the same output as [`Prompt::print`] to an output at once, for deterministic transcripts.
+	/// 
+	/// See [`Text::write_lines`] for how text is written.
+	pub fn write(&self, model: &PromptModel, display: bool, usable_choices: &Vec<&Choice>, text_context: &TextContext, out: &mut dyn Write) -> Result<()> {
+		let choices = match model {
+			PromptModel::Response => Some(Choice::display(usable_choices, &self.locked_choices(text_context)?, text_context)?),
+			_ => None
 		};
-		Ok(result)
+		let choices_first = text_context.config.settings.choices_first;
+		if let (true, Some(choices)) = (choices_first, &choices) {
+			writeln!(out, "{choices}\n")?;
+		}
+		if display && self.text.is_some() {
+			writeln!(out, "{}\n", self.render_text(text_context)?)?;
+		}
+		if let (false, Some(choices)) = (choices_first, &choices) {
+			writeln!(out, "{choices}\n")?;
+		}
+		Ok(())
+	}
+
+	/// Prints the choices display if the prompt is a [`PromptModel::Response`].
+	fn print_choices(model: &PromptModel, usable_choices: &Vec<&Choice>, locked_choices: &[&Choice], text_context: &TextContext) -> Result<()> {
+		if let PromptModel::Response = model {
+			println!("{}\n", Choice::display(usable_choices, locked_choices, text_context)?);
+		}
+		Ok(())
 	}
 
 	/// Returns the indices of any of this prompt's choices that jump to another prompt.
@@ -155,8 +511,8 @@ impl Prompt {
 	/// Finds all prompts that have choices that jump to a specific prompt name and file.
 	/// 
 	/// Uses [`Prompt::get_jumps_to`] to find the indices of the choices, if any.
-	pub fn external_jumps<'a>(name: &String, file: &String, prompts: &'a Prompts) -> HashMap<String, Vec<usize>> {
-		prompts.iter()
+	pub fn external_jumps(name: &String, file: &String, prompts: &Prompts) -> Result<HashMap<String, Vec<usize>>> {
+		let result = prompts.iter()?.into_iter()
     		.map(|(other_file_name, prompt_file)| {
 				prompt_file.iter()
 					.map(|(other_prompt_name, other_prompt)| {
@@ -166,16 +522,71 @@ impl Prompt {
     				.filter(|(_, choices)| !choices.is_empty())
 			})
     		.flatten()
-    		.collect()
+    		.collect();
+		Ok(result)
+	}
+
+	/// Describes where each of this prompt's choices leads, as used in [`Prompt::map`].
+	/// 
+	/// Each entry pairs a choice index with its jump target, or with the reason it has none: an `ending` or a `templated` jump.
+	pub fn choice_targets(&self, file: &str) -> Vec<(usize, Result<PathEntry, &'static str>)> {
+		self.choices.iter().enumerate()
+			.map(|(index, choice)| {
+				let target = match &choice.jump {
+					None => Err("ending"),
+					Some(jump) if jump.prompt.is_templatable() || jump.file.as_ref().map(|f| f.is_templatable()).unwrap_or(false) => Err("templated"),
+					Some(jump) => Ok(PathEntry {
+						file: jump.file.as_ref().map(|f| f.content.clone()).unwrap_or(file.to_owned()),
+						prompt: jump.prompt.content.clone()
+					})
+				};
+				(index, target)
+			})
+			.collect()
+	}
+
+	/// Renders a tree of the prompts reachable from a prompt within `depth` choices.
+	/// 
+	/// Prompts already shown elsewhere in the tree are marked rather than expanded again, keeping the output bounded.
+	pub fn map(prompts: &Prompts, path: &PathEntry, depth: usize) -> Result<String> {
+		fn branch(prompts: &Prompts, path: &PathEntry, depth: usize, indent: &str, seen: &mut HashSet<String>, lines: &mut Vec<String>) -> Result<()> {
+			let prompt = Prompt::get_from_path(prompts, path)?;
+			let targets = prompt.choice_targets(&path.file);
+			for (position, (index, target)) in targets.iter().enumerate() {
+				let last = position == targets.len() - 1;
+				let (connector, child_indent) = if last { ("└─", "   ") } else { ("├─", "│  ") };
+				match target {
+					Err(reason) => lines.push(format!("{indent}{connector} #{} ({reason})", index + 1)),
+					Ok(target) => {
+						let id = format!("{}/{}", target.file, target.prompt);
+						let repeated = seen.contains(&id);
+						let marker = if repeated { " (shown above)" } else { "" };
+						lines.push(format!("{indent}{connector} #{} -> {id}{marker}", index + 1));
+						if !repeated && depth > 1 {
+							seen.insert(id);
+							branch(prompts, target, depth - 1, &format!("{indent}{child_indent}"), seen, lines)?;
+						}
+					}
+				}
+			}
+			Ok(())
+		}
+		let id = format!("{}/{}", path.file, path.prompt);
+		let mut seen = HashSet::from([id.clone()]);
+		let mut lines = vec![id];
+		if depth > 0 {
+			branch(prompts, path, depth, "", &mut seen, &mut lines)?;
+		}
+		Ok(lines.join("\n"))
 	}
 
 	/// Returns a block of debug information about this prompt, 
 	/// including the ID, type, choices configuration, and other prompts that jump to this one.
-	pub fn debug_info(&self, name: &String, file: &String, prompts: &Prompts, notes: &Notes, text_context: &TextContext) -> Result<String> {
+	pub fn debug_info(&self, name: &String, file: &String, prompts: &Prompts, text_context: &TextContext) -> Result<String> {
 		let model = self.model(text_context)?;
 		let choices_amt = self.choices.len();
-		let usable_choices = self.usable_choices(notes, text_context)?.len();
-		let external_jumps: Vec<String> = Self::external_jumps(name, file, prompts).iter()
+		let usable_choices = self.usable_choices(text_context)?.len();
+		let external_jumps: Vec<String> = Self::external_jumps(name, file, prompts)?.iter()
     		.map(|(other_id, choices)| {
 				let indices: Vec<String> = choices.iter().map(|i| format!("#{}", i + 1)).collect();
 				format!("- {other_id}: {}", indices.join(", "))
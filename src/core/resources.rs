@@ -1,39 +1,170 @@
-use std::collections::{HashSet, BTreeMap};
+use std::collections::{BTreeSet, BTreeMap};
 
-use anyhow::Result;
+use anyhow::{Result, Context, anyhow};
 
-use crate::{loading::base::Loader, text::display::{Translations, TranslationFile}};
+use crate::{loading::base::Loader, text::{display::{Translations, TranslationFile, TranslationCoverage}, templating::TemplatableString, image::Images}};
 
 use super::{scripts::Scripts, prompt::{Prompts, Prompt}, audio::Audio, manifest::Manifest};
 
 pub type InfoPages = BTreeMap<String, String>;
-pub type UnlockedInfoPages = HashSet<String>;
+pub type UnlockedInfoPages = BTreeSet<String>;
 
 pub struct Resources {
 	pub prompts: Prompts,
 	pub translations: Translations,
 	pub info_pages: InfoPages,
 	pub scripts: Scripts,
-	pub audio: Option<Audio>
+	pub audio: Option<Audio>,
+	pub images: Images
 }
 
 impl Resources {
 	pub fn load(loader: &Loader, config: &Manifest) -> Result<Self> {
 		let result = Resources {
-			prompts: loader.load_content("prompts")?,
+			prompts: Prompts::load(loader, config.settings.lazy_prompts)?,
 			translations: loader.load_content("lang")?,
 			info_pages: loader.load_raw_content("info")?,
-			scripts: Scripts::load(loader)?,
-			audio: Audio::load(loader, config)?
+			scripts: Scripts::load(loader, &config.settings)?,
+			audio: Audio::load(loader, config)?,
+			images: Images::load(loader)?
 		};
+		loader.finish_progress();
 		Ok(result)
 	}
 
-	pub fn validate(&self) -> Result<()> {
-		let _ = Prompt::validate_all(&self.prompts)?;
+	/// Validates the manifest's entrypoint against the loaded resources.
+	/// 
+	/// The entrypoint is valid if:
+	/// - Its `path` points to a valid prompt
+	/// - Its `info` pages all exist
+	pub fn validate_entry(&self, config: &Manifest) -> Result<()> {
+		let entry = &config.entry;
+		let _ = Prompt::get_from_path(&self.prompts, &entry.path)
+			.with_context(|| "`path` points to invalid prompt")?;
+		let missing: Vec<&String> = entry.info_pages.iter().flatten()
+			.filter(|page| !self.info_pages.contains_key(*page))
+			.collect();
+		if !missing.is_empty() {
+			return Err(anyhow!("`info` references missing info pages: {}", itertools::join(missing, ", ")));
+		}
 		Ok(())
 	}
 
+	/// Checks that every sound, image, and script referenced by the entrypoint, translations, and, if `prompts`, every prompt is loaded,
+	/// along with the info pages required by choices.
+	/// 
+	/// Templated references can't be checked ahead of time and are skipped, as are sounds if the audio system isn't loaded.
+	pub fn validate_assets(&self, config: &Manifest, prompts: bool) -> Result<()> {
+		let entry = &config.entry;
+		let background = entry.background.iter().flatten();
+
+		let mut scripts: BTreeSet<String> = background.clone()
+			.map(|text| text.content.content.as_str())
+			.chain(self.translations.values().flat_map(|file| file.values()).map(String::as_str))
+			.flat_map(TemplatableString::script_components)
+			.collect();
+		let mut info_pages = BTreeSet::new();
+		let mut sounds: BTreeSet<&str> = background.clone()
+			.filter_map(|text| text.sound.as_ref())
+			.filter_map(|action| action.name.as_ref())
+			.filter(|name| !name.is_templatable())
+			.map(|name| name.content.as_str())
+			.chain(entry.sounds.iter().flatten().map(|action| action.name.as_str()))
+			.collect();
+		let mut images: BTreeSet<&str> = background.clone()
+			.filter_map(|text| text.image.as_ref())
+			.map(|image| image.name.as_str())
+			.collect();
+		if prompts {
+			scripts.extend(Prompt::referenced_scripts(&self.prompts)?);
+			sounds.extend(Prompt::sound_actions(&self.prompts)?.into_iter()
+				.filter_map(|action| action.name.as_ref())
+				.filter(|name| !name.is_templatable())
+				.map(|name| name.content.as_str()));
+			images.extend(Prompt::image_names(&self.prompts)?);
+			info_pages = Prompt::required_info_pages(&self.prompts)?;
+		}
+
+		let missing_scripts: Vec<String> = scripts.into_iter()
+			.filter(|script| !self.scripts.contains(script))
+			.collect();
+		// Assets are resolved the same way as when they are played or displayed, so the errors match
+		let missing_sounds = sounds.into_iter()
+			.filter_map(|sound| self.audio.as_ref().and_then(|audio| audio.sounds.get(sound).err()));
+		let missing_images = images.into_iter()
+			.filter_map(|image| self.images.files.get(image).err());
+		let missing_assets: Vec<String> = missing_sounds.chain(missing_images)
+			.map(|err| err.to_string())
+			.collect();
+		let missing_info_pages: Vec<&str> = info_pages.into_iter()
+			.filter(|page| !self.info_pages.contains_key(*page))
+			.collect();
+		if missing_scripts.is_empty() && missing_assets.is_empty() && missing_info_pages.is_empty() {
+			return Ok(());
+		}
+		let mut lines = Vec::new();
+		if !missing_scripts.is_empty() {
+			lines.push(format!("Missing scripts: {}", missing_scripts.join(", ")));
+		}
+		lines.extend(missing_assets);
+		if !missing_info_pages.is_empty() {
+			lines.push(format!("Missing info pages: {}", missing_info_pages.join(", ")));
+		}
+		Err(anyhow!("{}", lines.join("\n")))
+	}
+
+	/// Validates loaded resources.
+	/// 
+	/// Validating prompts parses every prompt file, so this is skipped for lazily loaded prompts outside of debug mode.
+	/// The entrypoint and the assets it references are always validated.
+	pub fn validate(&self, config: &Manifest) -> Result<()> {
+		self.validate_entry(config)
+			.with_context(|| "Failed to validate manifest entrypoint")?;
+		let all_prompts = !config.settings.lazy_prompts || config.settings.debug;
+		self.validate_assets(config, all_prompts)
+			.with_context(|| "Failed to find referenced assets")?;
+		if all_prompts {
+			Prompt::validate_all(&self.prompts, &self.scripts, config)?;
+		}
+		Ok(())
+	}
+
+	/// Checks every translation file against the translation keys used by prompts and choices.
+	/// 
+	/// A displayed string counts as a key if any translation file defines it or if it looks like one
+	/// according to [`TemplatableString::is_str_translation_key`].
+	pub fn translation_coverage(&self) -> Result<BTreeMap<&String, TranslationCoverage<'_>>> {
+		let used: BTreeSet<&str> = Prompt::displayed_strings(&self.prompts)?.into_iter()
+			.filter(|content| {
+				TemplatableString::is_str_translation_key(content) || self.translations.values().any(|file| file.contains_key(*content))
+			})
+			.collect();
+		let result = self.translations.iter()
+			.map(|(lang, file)| (lang, TranslationCoverage::new(&used, file)))
+			.collect();
+		Ok(result)
+	}
+
+	/// Finds the loaded language closest to a locale such as `en-US`.
+	/// 
+	/// An exact match, such as `en_us`, is preferred over one that only shares the language, such as `en` or `en_gb`.
+	pub fn closest_lang(&self, locale: &str) -> Option<&String> {
+		let locale = locale.to_lowercase().replace('-', "_");
+		let language = locale.split('_').next().unwrap_or(&locale);
+		self.translations.get_key_value(&locale).map(|(key, _)| key)
+			.or_else(|| {
+				self.translations.keys()
+					.find(|key| key.as_str() == language || key.split('_').next() == Some(language))
+			})
+	}
+
+	/// Picks a language for a new player based on the system locale, if any loaded language matches it.
+	/// Only used when the manifest doesn't set a `lang`.
+	pub fn system_lang(&self) -> Option<String> {
+		sys_locale::get_locale()
+			.and_then(|locale| self.closest_lang(&locale).cloned())
+	}
+
 	pub fn lang_file(&self, lang: &str) -> Option<&TranslationFile> {
 		self.translations.get(lang)
 	}
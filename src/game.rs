@@ -1,3 +1,5 @@
+pub mod auto;
 pub mod gloop;
 pub mod input;
-pub mod main;
\ No newline at end of file
+pub mod main;
+pub mod signals;
\ No newline at end of file
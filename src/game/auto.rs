@@ -0,0 +1,199 @@
+use std::{fmt::Display, collections::BTreeSet, io::Write};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use rand::{Rng, thread_rng};
+
+use crate::{core::{manifest::Manifest, player::{Player, PathEntry}, prompt::{Prompt, PromptModel}, resources::Resources, choice::Choice}, text::display::Text};
+
+use super::input::VariableInputResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+/// How choices are picked during an automatic playthrough.
+pub enum Strategy {
+	/// Picks a random usable choice.
+	Random,
+	/// Always picks the first usable choice.
+	First,
+	/// Always picks the last usable choice.
+	Last
+}
+
+impl Strategy {
+	/// Picks the index of a choice out of a non-empty amount of usable choices.
+	fn pick(&self, choices: usize) -> usize {
+		use Strategy::*;
+		match self {
+			Random => thread_rng().gen_range(0..choices),
+			First => 0,
+			Last => choices - 1
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// How an automatic playthrough finished.
+pub enum Outcome {
+	/// An ending was reached from the prompt with the contained ID.
+	Ending(String),
+	/// The prompt with the contained ID had no usable choices.
+	Stuck(String),
+	/// The maximum amount of steps was exceeded, likely due to a loop.
+	Exhausted
+}
+
+impl Display for Outcome {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		use Outcome::*;
+		match self {
+			Ending(id) => write!(f, "Ending at {id}"),
+			Stuck(id) => write!(f, "No usable choices at {id}"),
+			Exhausted => write!(f, "No ending reached")
+		}
+	}
+}
+
+/// The result of an automatic playthrough.
+pub struct Playthrough {
+	/// The IDs of each visited prompt, in order.
+	pub path: Vec<String>,
+	pub outcome: Outcome
+}
+
+#[derive(Default)]
+/// The prompts and choices visited across any number of automatic playthroughs.
+/// 
+/// Unlike the player history, this is never truncated.
+pub struct Coverage {
+	/// The IDs of visited prompts.
+	prompts: BTreeSet<String>,
+	/// The IDs of prompts paired with the indices of their taken choices.
+	choices: BTreeSet<(String, usize)>
+}
+
+impl Coverage {
+	/// Formats a percentage of visited content.
+	fn percentage(visited: usize, total: usize) -> f64 {
+		if total == 0 { 100.0 } else { visited as f64 / total as f64 * 100.0 }
+	}
+
+	/// Creates a report of how much of the game's prompts and choices were visited,
+	/// listing every prompt and choice that never was.
+	pub fn report(&self, resources: &Resources) -> Result<String> {
+		let mut prompts = 0;
+		let mut choices = 0;
+		let mut unvisited = Vec::new();
+		for (file, prompt_file) in resources.prompts.iter()? {
+			for (name, prompt) in prompt_file {
+				let id = format!("{file}/{name}");
+				prompts += 1;
+				choices += prompt.choices.len();
+				if !self.prompts.contains(&id) {
+					unvisited.push(format!("- {id}"));
+					continue;
+				}
+				for index in 0..prompt.choices.len() {
+					if !self.choices.contains(&(id.clone(), index)) {
+						unvisited.push(format!("- {id} choice #{}", index + 1));
+					}
+				}
+			}
+		}
+		let mut result = format!(
+			"Prompts visited: {}/{prompts} ({:.1}%)\nChoices taken: {}/{choices} ({:.1}%)",
+			self.prompts.len(), Self::percentage(self.prompts.len(), prompts),
+			self.choices.len(), Self::percentage(self.choices.len(), choices)
+		);
+		if !unvisited.is_empty() {
+			result.push_str(&format!("\n\nNever visited:\n{}", unvisited.join("\n")));
+		}
+		Ok(result)
+	}
+}
+
+/// The value submitted to prompts that take player input.
+pub const INPUT_VALUE: &str = "auto";
+
+/// The readable ID of a prompt path.
+fn prompt_id(path: &PathEntry) -> String {
+	format!("{}/{}", path.file, path.prompt)
+}
+
+/// Plays a new game without any output, picking choices using a [`Strategy`] until an ending is reached,
+/// a prompt has no usable choices, or `max_steps` prompts have been visited.
+/// 
+/// Prompts that take input receive [`INPUT_VALUE`]. Sound channels are disabled.
+/// Visited prompts and taken choices are recorded in the `coverage`.
+/// 
+/// If a `transcript` is given, the displayed text and the submitted input are written to it without animation,
+/// so that playthroughs with a deterministic strategy produce the same transcript every time.
+pub fn play(config: &Manifest, resources: &Resources, strategy: Strategy, max_steps: usize, coverage: &mut Coverage, mut transcript: Option<&mut dyn Write>) -> Result<Playthrough> {
+	let mut player = Player::new(config);
+	player.channels.clear();
+	player.began = true;
+	let mut path = Vec::new();
+	for _ in 0..max_steps {
+		let entry_path = player.latest_entry()?.path.clone();
+		let id = prompt_id(&entry_path);
+		path.push(id.clone());
+		coverage.prompts.insert(id.clone());
+		let prompt = Prompt::get_from_path(&resources.prompts, &entry_path)?;
+		player.enter(prompt, config, resources)?;
+		let text_context = player.text_context(config, resources);
+		let model = prompt.model(&text_context)?;
+		let choices = prompt.usable_choices(&text_context)?;
+		if choices.is_empty() {
+			return Ok(Playthrough { path, outcome: Outcome::Stuck(id) });
+		}
+		if let Some(out) = transcript.as_deref_mut() {
+			prompt.write(&model, player.latest_entry()?.display, &choices, &text_context, out)?;
+		}
+		let choice: &Choice = match &model {
+			PromptModel::Ending(lines) => {
+				coverage.choices.insert((id.clone(), 0));
+				if let Some(out) = transcript.as_deref_mut() {
+					Text::write_lines(lines, &text_context, out)?;
+				}
+				return Ok(Playthrough { path, outcome: Outcome::Ending(id) });
+			},
+			PromptModel::Input(fields) => {
+				coverage.choices.insert((id.clone(), 0));
+				if let Some(out) = transcript.as_deref_mut() {
+					for _ in fields {
+						writeln!(out, "> {INPUT_VALUE}")?;
+					}
+					writeln!(out)?;
+				}
+				// Mirrors the game loop, which applies input variables after the history entry is created
+				let results: Vec<VariableInputResult> = fields.iter()
+					.map(|(name, _)| VariableInputResult(name.clone(), INPUT_VALUE.to_owned()))
+					.collect();
+				player.choose(choices[0], &results, config, &model, resources, &text_context)?;
+				for result in &results {
+					player.variables.insert(result.0.clone(), result.1.clone());
+				}
+				player.try_push_log(choices[0], config, resources)?;
+				continue;
+			},
+			PromptModel::Redirect(choice) => choice,
+			PromptModel::Response => {
+				let index = strategy.pick(choices.len());
+				if let Some(out) = transcript.as_deref_mut() {
+					writeln!(out, "> {}\n", config.settings.choice_labels.label(index + 1, choices.len()))?;
+				}
+				choices[index]
+			}
+		};
+		if let Some(index) = prompt.choices.iter().position(|other| std::ptr::eq(other, choice)) {
+			coverage.choices.insert((id.clone(), index));
+		}
+		player.transition(choice, &[], config, &model, resources, &text_context)?;
+		if let (Some(out), Some(ending)) = (transcript.as_deref_mut(), &choice.ending) {
+			Text::write_lines(ending, &text_context, out)?;
+		}
+		if choice.ending.is_some() {
+			return Ok(Playthrough { path, outcome: Outcome::Ending(id) });
+		}
+	}
+	Ok(Playthrough { path, outcome: Outcome::Exhausted })
+}
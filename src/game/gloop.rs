@@ -1,34 +1,38 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use result::OptionResultExt;
 
-use crate::{core::{player::Player, manifest::Manifest, choice::Choice, prompt::PromptModel, resources::Resources}, cmd::runtime::{RuntimeCommand, CommandResult}, game::input::{InputContext, InputResult}, loading::saves::SaveManager, text::{display::Text, context::TextContext}};
+use crate::{core::{player::Player, manifest::Manifest, choice::Choice, prompt::PromptModel, resources::Resources}, cmd::runtime::{RuntimeCommand, CommandResult}, game::input::{InputContext, InputResult, VariableField, VariableInputResult}, loading::saves::SaveManager, text::{display::Text, context::TextContext}};
 
 use super::input::InputController;
 
 pub enum GameLoopResult {
 	Retry(bool),
 	Continue,
-	Shutdown(bool)
+	Shutdown(bool),
+	/// An ending was reached whose choice enables the ending menu.
+	EndingMenu
 }
 
-pub fn handle_quit(shutdown: bool) -> GameLoopResult {
+/// Shuts down on a quit signal, asking the player for confirmation first unless `shutdown` is already set.
+/// 
+/// The question names the configured [`QuitBinding`](crate::core::manifest::QuitBinding), since giving the quit signal again while asked answers yes.
+pub fn handle_quit(shutdown: bool, input: &mut InputController) -> Result<GameLoopResult> {
 	use GameLoopResult::*;
-	if shutdown { 
-		Shutdown(false)
+	if shutdown || input.confirm(&format!("Quit the game? {} again or answer", input.quit_binding().instruction()))? {
+		Ok(Shutdown(false))
 	}
-	else { 
-		println!("Signal quit again or use '.quit' to exit");
-		Retry(true)
+	else {
+		Ok(Retry(true))
 	}
 }
 
 pub fn handle_choice(choice: &Choice, config: &Manifest, player: &mut Player, resources: &Resources, model: &PromptModel, text_context: &TextContext) -> Result<GameLoopResult> {
 	use GameLoopResult::*;
-	player.choose_full(choice, None, config, resources, model, text_context)?;
+	player.choose_full(choice, &[], config, resources, model, text_context)?;
 	if let Some(ending) = &choice.ending {
 		println!();
 		Text::print_lines(ending, text_context)?;
-		return Ok(Shutdown(true));
+		return Ok(if choice.ending_menu { EndingMenu } else { Shutdown(true) });
 	}
 	Ok(Continue)
 }
@@ -51,34 +55,90 @@ pub fn handle_command(parse: Result<RuntimeCommand>, config: &Manifest, player:
 	Ok(GameLoopResult::Retry(parse.is_ok()))
 }
 
-pub fn take_input(input: &mut InputController, context: &InputContext, config: &Manifest, player: &mut Player, saves: &SaveManager, resources: &Resources, model: &PromptModel, text_context: &TextContext, choices: &Vec<&Choice>) -> Result<GameLoopResult> {
+/// The state of a prompt that awaits input, resolved once per prompt by the game loop.
+pub struct PromptInput<'a> {
+	pub model: &'a PromptModel<'a>,
+	pub text_context: &'a TextContext<'a>,
+	/// The usable choices, in display order.
+	pub choices: &'a [&'a Choice],
+	/// The usable choices that can be selected by typing their keyword.
+	pub keywords: &'a [(String, &'a Choice)],
+	/// Variable input results accumulated until every field of a [`PromptModel::Input`] is filled.
+	pub inputs: Vec<VariableInputResult>
+}
+
+impl<'a> PromptInput<'a> {
+	pub fn new(model: &'a PromptModel<'a>, text_context: &'a TextContext<'a>, choices: &'a [&'a Choice], keywords: &'a [(String, &'a Choice)]) -> Self {
+		PromptInput { model, text_context, choices, keywords, inputs: Vec::new() }
+	}
+}
+
+/// Takes a line of input and handles its result.
+/// 
+/// Variable input results are accumulated in the prompt's `inputs` until every field of the [`PromptModel::Input`] is filled,
+/// after which the choice is taken.
+pub fn take_input(input: &mut InputController, context: &InputContext, config: &Manifest, player: &mut Player, saves: &SaveManager, resources: &Resources, prompt: &mut PromptInput) -> Result<GameLoopResult> {
 	use GameLoopResult::*;
+	let PromptInput { model, text_context, choices, keywords, inputs } = prompt;
 	let result = match input.take(context) {
 		Err(err) => {
 			println!("{err}");
 			Retry(true)
 		},
 		Ok(result) => match result {
-			InputResult::Quit(shutdown) => handle_quit(shutdown),
+			InputResult::Quit(shutdown) => handle_quit(shutdown, input)?,
 			InputResult::Choice(i) => handle_choice(choices[i - 1], config, player, resources, model, text_context)?,
+			InputResult::Keyword(i) => handle_choice(keywords[i].1, config, player, resources, model, text_context)?,
 			InputResult::Variable(result) => {
+				inputs.push(result);
+				let fields = match model {
+					PromptModel::Input(fields) => fields.len(),
+					_ => 1
+				};
+				if inputs.len() < fields {
+					return Ok(Retry(false));
+				}
 				// Modify variables after the choose call since history entries are sensitive to this order
-				player.choose(choices[0], Some(&result), config, model, resources, text_context)?;
-				player.variables.insert(result.0.clone(), result.1.clone());
+				player.choose(choices[0], inputs, config, model, resources, text_context)?;
+				for result in inputs.iter() {
+					player.variables.insert(result.0.clone(), result.1.clone());
+				}
 				player.try_push_log(choices[0], config, resources)?;
 				Continue
 			},
+			// Confirmations are only awaited through `InputController::confirm`
+			InputResult::Confirm(_) => Retry(true),
 			InputResult::Command(parse) => handle_command(parse, config, player, saves, resources, text_context)?
 		}
 	};
 	Ok(result)
 }
 
-pub fn next_input_context(model: &PromptModel, choices: &Vec<&Choice>, text_context: &TextContext) -> Result<Option<InputContext>> {
+/// Resolves the input awaited by a prompt. For [`PromptModel::Input`] prompts, this is the first field not yet filled.
+pub fn next_input_context(prompt: &PromptInput) -> Result<Option<InputContext>> {
 	use PromptModel::*;
-	let result = match &model {
-		Response => Some(InputContext::Choices(choices.len())),
-		&Input(name, prompt) => Some(InputContext::Variable(name.clone(), prompt.map(|s| s.fill(text_context)).invert()?)),
+	let PromptInput { model, text_context, choices, keywords, inputs } = prompt;
+	let field = inputs.len();
+	let settings = &text_context.config.settings;
+	let indicators = &settings.input_indicators;
+	let result = match model {
+		Response => {
+			let indicator = indicators.choice.as_ref().map(|s| s.fill(text_context)).invert()?;
+			let keywords = keywords.iter().map(|(keyword, _)| keyword.clone()).collect();
+			Some(InputContext::Choices(choices.len(), settings.choice_labels, keywords, indicator.unwrap_or(InputContext::PROMPT.to_owned())))
+		},
+		Input(fields) => {
+			let (name, input) = fields.get(field).ok_or(anyhow!("No input field at index {field}"))?;
+			let default = input.default.as_ref().map(|default| default.fill(text_context)).invert()?;
+			// The input's own text takes precedence over the configured indicator
+			let indicator = match (&input.text, &default) {
+				(Some(text), Some(default)) => format!("{} ({default}): ", text.fill(text_context)?),
+				(Some(text), None) => format!("{}: ", text.fill(text_context)?),
+				(None, _) => indicators.variable.as_ref().map(|s| s.fill(text_context)).invert()?.unwrap_or(InputContext::PROMPT.to_owned())
+			};
+			let field = VariableField { name: name.clone(), default, max_length: input.max_length };
+			Some(InputContext::Variable(field, indicator))
+		},
 		_ => None
 	};
 	Ok(result)
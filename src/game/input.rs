@@ -1,28 +1,65 @@
+use std::{io::{IsTerminal, Write}, collections::HashMap, sync::mpsc::{self, RecvTimeoutError}};
+
 use anyhow::{Result, anyhow};
-use clap::Parser;
-use rustyline::DefaultEditor;
+use clap::{Parser, CommandFactory};
+use crossterm::{event::{self, Event, KeyEventKind}, style::Stylize, terminal::{self, Clear, ClearType}};
+use rustyline::{DefaultEditor, KeyEvent, KeyCode, Modifiers, Cmd};
 
-use crate::{core::{player::VariableEntry, choice::Variables}, cmd::runtime::RuntimeCommand};
+use crate::{game::signals, core::{player::VariableEntry, choice::{Variables, ChoiceLabels}, manifest::{QuitSettings, QuitBinding, Settings}}, cmd::runtime::RuntimeCommand};
 
 #[derive(Debug)]
 pub struct InputController {
-	rl: DefaultEditor,
-	quit: bool
+	/// The line editor, lent to a reader thread while awaiting input and lost if a signal ends the read.
+	rl: Option<DefaultEditor>,
+	quit: bool,
+	quit_settings: QuitSettings,
+	/// Command aliases mapped to the command lines they expand to.
+	aliases: HashMap<String, String>
+}
+
+/// A variable input awaited from the player.
+pub struct VariableField {
+	pub name: String,
+	/// The value used when the input is left empty, if any.
+	pub default: Option<String>,
+	/// The maximum amount of characters accepted, if any.
+	pub max_length: Option<usize>
+}
+
+impl VariableField {
+	/// Accepts a line of input as this field's value, using the default for empty input.
+	fn accept(&self, line: String) -> Result<VariableInputResult> {
+		let value = match (line.is_empty(), &self.default) {
+			(true, Some(default)) => default.clone(),
+			(true, None) => return Err(anyhow!("Input cannot be empty")),
+			(false, _) => line
+		};
+		if let Some(max) = self.max_length {
+			if value.chars().count() > max {
+				return Err(anyhow!("Input must be at most {max} characters"));
+			}
+		}
+		Ok(VariableInputResult(self.name.clone(), value))
+	}
 }
 
+/// The kind of input awaited, along with the indicator displayed while awaiting it.
 pub enum InputContext {
-	Choices(usize),
-	Variable(String, Option<String>)
+	/// The amount of displayed choices, their labels, and the lowercase keywords of any choices that can be typed.
+	Choices(usize, ChoiceLabels, Vec<String>, String),
+	Variable(VariableField, String),
+	/// A yes or no question, resolving to [`InputResult::Confirm`].
+	Confirm(String)
 }
 
 impl InputContext {
-	const PROMPT: &'static str = "> ";
+	/// The indicator used when none is configured.
+	pub const PROMPT: &'static str = "> ";
 
-	pub fn prompt(&self) -> String {
+	pub fn prompt(&self) -> &str {
 		use InputContext::*;
 		match self {
-			Choices(_) => Self::PROMPT.to_owned(),
-			Variable(_, prompt) => prompt.clone().map(|s| format!("{s}: ")).unwrap_or(Self::PROMPT.to_owned())
+			Choices(.., indicator) | Variable(_, indicator) | Confirm(indicator) => indicator
 		}
 	}
 }
@@ -38,18 +75,67 @@ impl VariableInputResult {
 pub enum InputResult {
 	Quit(bool),
 	Choice(usize),
+	/// The index of a typed keyword within [`InputContext::Choices`].
+	Keyword(usize),
 	Variable(VariableInputResult),
+	Confirm(bool),
 	Command(Result<RuntimeCommand>)
 }
 
 impl InputController {
-	pub fn new() -> Result<Self> {
+	/// Creates an input controller, binding the configured quit key if necessary.
+	/// 
+	/// Command aliases that shadow a built-in command are reported and ignored.
+	pub fn new(settings: &Settings) -> Result<Self> {
+		let mut rl = DefaultEditor::new()?;
+		if settings.quit.binding == QuitBinding::Esc {
+			rl.bind_sequence(KeyEvent(KeyCode::Esc, Modifiers::NONE), Cmd::Interrupt);
+		}
+		let command = RuntimeCommand::command();
+		let (aliases, conflicts): (HashMap<String, String>, HashMap<String, String>) = settings.aliases.clone().into_iter()
+			.partition(|(alias, _)| command.find_subcommand(alias).is_none());
+		for alias in conflicts.keys() {
+			log::warn!("Ignoring alias '{alias}' since it shadows a built-in command");
+		}
 		Ok(Self {
-			rl: DefaultEditor::new()?,
-			quit: false
+			rl: Some(rl),
+			quit: false,
+			quit_settings: settings.quit.clone(),
+			aliases
 		})
 	}
 
+	/// Replaces an aliased command name at the start of a command line with its expansion, keeping any arguments.
+	/// 
+	/// For example, with the alias `q: quit`, the line `.q` becomes `.quit`.
+	fn expand_alias(&self, line: String) -> String {
+		let Some(command) = line.strip_prefix('.') else {
+			return line;
+		};
+		let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+		match self.aliases.get(name) {
+			Some(expansion) => {
+				let expansion = expansion.strip_prefix('.').unwrap_or(expansion);
+				format!(".{expansion} {args}").trim_end().to_owned()
+			},
+			None => line
+		}
+	}
+
+	/// The configured input that signals the game to quit.
+	pub fn quit_binding(&self) -> &QuitBinding {
+		&self.quit_settings.binding
+	}
+
+	/// Records a quit signal, returning whether the game should shut down.
+	/// 
+	/// Unless the quit settings disable confirmation, the first signal asks for confirmation and a second consecutive one shuts down.
+	fn signal_quit(&mut self) -> InputResult {
+		let shutdown = self.quit || !self.quit_settings.confirm;
+		self.quit = !shutdown;
+		InputResult::Quit(shutdown)
+	}
+
 	pub fn parse_command(line: String) -> Result<RuntimeCommand> {
 		// Split line into command + arguments after '.' starting character
 		let args: Vec<String> = line.strip_prefix(".").unwrap().split(" ")
@@ -59,44 +145,216 @@ impl InputController {
 			.map_err(|e| anyhow!(e))
 	}
 
+	/// Reduces a line of input to its first non-blank line, replacing tabs with spaces and stripping any other control characters.
+	/// 
+	/// Pasted text arrives as a single line with embedded line breaks, which must not be taken as multiple inputs.
+	/// Terminal escape sequences such as the bracketed paste markers `ESC[200~` and `ESC[201~` are removed entirely.
+	/// Returns the sanitized line and whether any further lines were discarded.
+	pub fn sanitize(line: &str) -> (String, bool) {
+		let mut lines = line.split(['\n', '\r']).filter(|line| !Self::strip_controls(line).trim().is_empty());
+		let first = lines.next().unwrap_or("");
+		let sanitized = Self::strip_controls(first);
+		(sanitized.trim().to_owned(), lines.next().is_some())
+	}
+
+	/// Replaces tabs with spaces and removes control characters along with any `ESC[` control sequences they begin.
+	fn strip_controls(line: &str) -> String {
+		let mut result = String::with_capacity(line.len());
+		let mut chars = line.chars().peekable();
+		while let Some(c) = chars.next() {
+			match c {
+				'\t' => result.push(' '),
+				'\x1b' if chars.next_if_eq(&'[').is_some() => {
+					// Parameters and intermediate bytes precede a single final byte in `@..=~`
+					while chars.next().is_some_and(|next| !('@'..='~').contains(&next)) {}
+				},
+				c if c.is_control() => (),
+				c => result.push(c)
+			}
+		}
+		result
+	}
+
+	/// Parses a yes or no answer, accepting `y`, `yes`, `n`, and `no` case-insensitively.
+	fn parse_confirm(line: &str) -> Result<bool> {
+		match line.to_lowercase().as_str() {
+			"y" | "yes" => Ok(true),
+			"n" | "no" => Ok(false),
+			_ => Err(anyhow!("Input must be 'y' or 'n'"))
+		}
+	}
+
 	pub fn handle_line(line: String, context: &InputContext) -> Result<InputResult> {
 		if line.is_empty() {
+			// Variable fields may have a default for empty input
+			if let InputContext::Variable(field, _) = context {
+				return Ok(InputResult::Variable(field.accept(line)?));
+			}
 			return Err(anyhow!("Input cannot be empty"));
 		}
+		if let InputContext::Confirm(_) = context {
+			return Ok(InputResult::Confirm(Self::parse_confirm(&line)?));
+		}
 		if line.starts_with(".") {
 			return Ok(InputResult::Command(Self::parse_command(line)))
 		}
 		match context {
-			&InputContext::Choices(choices) => {
-				let choice = line.parse::<usize>()
-					.map_err(|_| anyhow!("Input must be a number"))?;
-				if choice < 1 || choice > choices {
-					return Err(anyhow!("Input out of range"))
+			InputContext::Choices(choices, labels, keywords, _) => {
+				// Keywords take precedence over labels
+				let typed = line.trim().to_lowercase();
+				match keywords.iter().position(|keyword| *keyword == typed) {
+					Some(index) => Ok(InputResult::Keyword(index)),
+					None => Ok(InputResult::Choice(labels.parse(&line, *choices)?))
 				}
-				Ok(InputResult::Choice(choice))
+			},
+			InputContext::Variable(field, _) => Ok(InputResult::Variable(field.accept(line)?)),
+			InputContext::Confirm(_) => unreachable!()
+		}
+	}
+
+	/// Asks a yes or no question, prompting again until the answer is unambiguous.
+	/// 
+	/// Commands aren't accepted. A quit signal that would shut down the game counts as a yes, and any other as a no.
+	pub fn confirm(&mut self, question: &str) -> Result<bool> {
+		let context = InputContext::Confirm(format!("{question} (y/n) "));
+		loop {
+			match self.take(&context) {
+				Err(err) => println!("{err}"),
+				Ok(InputResult::Confirm(answer)) => return Ok(answer),
+				Ok(InputResult::Quit(shutdown)) => return Ok(shutdown),
+				Ok(_) => {}
 			}
-			InputContext::Variable(name, _) => Ok(InputResult::Variable(VariableInputResult(name.clone(), line)))
 		}
 	}
 
+	/// Prints a hint and blocks until the player presses any key, then clears the hint.
+	/// 
+	/// Resolves immediately if standard input isn't an interactive terminal.
+	pub fn wait_for_key(hint: &str) -> Result<()> {
+		if !std::io::stdin().is_terminal() {
+			return Ok(());
+		}
+		print!("{}", hint.dark_grey());
+		std::io::stdout().flush()?;
+		terminal::enable_raw_mode()?;
+		let result = loop {
+			if signals::shutdown_requested() {
+				break Ok(());
+			}
+			match event::poll(signals::POLL_INTERVAL).and_then(|ready| if ready { event::read().map(Some) } else { Ok(None) }) {
+				Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => break Ok(()),
+				Ok(_) => continue,
+				Err(err) => break Err(err)
+			}
+		};
+		terminal::disable_raw_mode()?;
+		print!("\r{}", Clear(ClearType::CurrentLine));
+		std::io::stdout().flush()?;
+		Ok(result?)
+	}
+
+	/// Reads a line on a separate thread, returning `None` if a signal requests a shutdown before it is submitted.
+	/// 
+	/// In a terminal, the read is wrapped in [`crossterm`]'s raw mode so that [`signals::restore_terminal`] can leave
+	/// the line editor's raw mode even when the read is abandoned. The abandoned read keeps the line editor,
+	/// so any later read also returns `None`.
+	fn read_line(&mut self, prompt: &str) -> Result<Option<rustyline::Result<String>>> {
+		let Some(mut rl) = self.rl.take() else {
+			return Ok(None);
+		};
+		let terminal = std::io::stdin().is_terminal();
+		if terminal {
+			terminal::enable_raw_mode()?;
+		}
+		let prompt = prompt.to_owned();
+		let (sender, receiver) = mpsc::channel();
+		std::thread::spawn(move || {
+			let line = rl.readline(&prompt);
+			let _ = sender.send((rl, line));
+		});
+		let result = loop {
+			match receiver.recv_timeout(signals::POLL_INTERVAL) {
+				Ok((rl, line)) => {
+					self.rl = Some(rl);
+					break Some(line);
+				},
+				Err(RecvTimeoutError::Timeout) if !signals::shutdown_requested() => continue,
+				Err(_) => break None
+			}
+		};
+		if terminal {
+			signals::restore_terminal();
+			// The editor ends lines without a carriage return while output processing is disabled in raw mode
+			print!("\r");
+		}
+		if result.is_none() {
+			println!();
+		}
+		Ok(result)
+	}
+
+	/// Awaits input for the given context.
+	/// 
+	/// A signal requesting a shutdown ends the read as a quit signal that shuts down the game.
 	pub fn take(&mut self, context: &InputContext) -> Result<InputResult> {
-		use InputResult::*;
-		match self.rl.readline(&context.prompt()) {
-			Ok(line) => {
+		let Some(read) = self.read_line(context.prompt())? else {
+			return Ok(InputResult::Quit(true));
+		};
+		match read {
+			Ok(raw) => {
+				let (line, discarded) = Self::sanitize(&raw);
+				if discarded {
+					println!("{}", "Only the first line of the pasted input was used".dark_grey());
+				}
+				if let QuitBinding::Word(word) = &self.quit_settings.binding {
+					if &line == word {
+						return Ok(self.signal_quit());
+					}
+				}
 				if self.quit {
 					self.quit = false;
 				}
-				let result = Self::handle_line(line.trim().to_owned(), context)?;
-				self.rl.add_history_entry(line)?;
+				let result = Self::handle_line(self.expand_alias(line.clone()), context)?;
+				if let Some(rl) = &mut self.rl {
+					rl.add_history_entry(line)?;
+				}
 				Ok(result)
 			},
-			Err(_) => {
-				let result = Quit(self.quit);
-				if !self.quit {
-					self.quit = true;
-				}
-				return Ok(result);
-			}
+			// Interrupts and end-of-file signals always count, since the terminal sends them regardless of the binding
+			Err(_) => Ok(self.signal_quit())
 		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::InputController;
+
+	#[test]
+	fn single_line() {
+		assert_eq!(InputController::sanitize("  hello world "), ("hello world".to_owned(), false));
+		assert_eq!(InputController::sanitize("a\tb"), ("a b".to_owned(), false));
+	}
+
+	#[test]
+	fn line_breaks() {
+		assert_eq!(InputController::sanitize("first\r\nsecond"), ("first".to_owned(), true));
+		assert_eq!(InputController::sanitize("first\nsecond\nthird"), ("first".to_owned(), true));
+		assert_eq!(InputController::sanitize("first\r\n"), ("first".to_owned(), false));
+		assert_eq!(InputController::sanitize("\n\r\n  \nfirst"), ("first".to_owned(), false));
+	}
+
+	#[test]
+	fn escape_sequences() {
+		assert_eq!(InputController::sanitize("\x1b[200~pasted\x1b[201~"), ("pasted".to_owned(), false));
+		assert_eq!(InputController::sanitize("\x1b[31mred\x1b[0m text"), ("red text".to_owned(), false));
+		assert_eq!(InputController::sanitize("\x1b[200~first\nsecond\x1b[201~"), ("first".to_owned(), true));
+		assert_eq!(InputController::sanitize("\x1b[200~\nfirst\x1b[201~"), ("first".to_owned(), false));
+	}
+
+	#[test]
+	fn control_characters() {
+		assert_eq!(InputController::sanitize("a\x07b\x00c\x1bd"), ("abcd".to_owned(), false));
+		assert_eq!(InputController::sanitize("\x1b["), ("".to_owned(), false));
+	}
+}
@@ -1,18 +1,65 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
 use anyhow::{Result, anyhow};
+use result::OptionResultExt;
+use strum::Display;
+
+use crate::{core::{prompt::{Prompt, PromptModel}, manifest::Manifest, player::Player, resources::Resources, choice::{Notes, Variables}}, loading::saves::{SaveManager, SaveSelection}, text::{context::TextContext, display::Text}};
+
+use super::{gloop::{next_input_context, take_input, GameLoopResult, PromptInput}, input::InputController, signals};
 
-use crate::{core::{prompt::{Prompt, PromptModel}, manifest::Manifest, player::Player, resources::Resources}, loading::saves::SaveManager, text::{context::TextContext, display::Text}};
+#[derive(Default)]
+/// The choices made in the [`first_run_menu`].
+pub struct FirstRunSetup {
+	pub lang: Option<String>,
+	pub profile: Option<String>
+}
 
-use super::{gloop::{next_input_context, take_input, GameLoopResult}, input::InputController};
+/// Asks for a display language and, if the game enables profiles, a profile name when the game is run for the first time.
+/// 
+/// The menu is skipped when there is only one language to choose from and profiles aren't enabled.
+pub fn first_run_menu(config: &Manifest, resources: &Resources) -> Result<FirstRunSetup> {
+	let choose_lang = resources.translations.len() > 1;
+	if !(choose_lang || config.settings.profiles) || !SaveManager::is_first_run(config)? {
+		return Ok(FirstRunSetup::default());
+	}
+	let lang = if choose_lang {
+		let question = requestty::Question::select("Select a language")
+			.choices(resources.translations.keys())
+			.build();
+		Some(requestty::prompt_one(question)?.as_list_item().unwrap().text.clone())
+	}
+	else {
+		None
+	};
+	let profile = if config.settings.profiles {
+		let question = requestty::Question::input("Profile name (leave empty for the default profile)")
+			.validate(|name, _| {
+				if !name.is_empty() && !sanitize_filename::is_sanitized(name) {
+					return Err("Invalid profile name".to_owned())
+				}
+				Ok(())
+			})
+			.build();
+		Some(requestty::prompt_one(question)?.as_string().unwrap().to_owned())
+			.filter(|name| !name.is_empty())
+	}
+	else {
+		None
+	};
+	println!();
+	Ok(FirstRunSetup { lang, profile })
+}
 
 pub fn first_play_init(config: &Manifest, player: &mut Player, resources: &Resources) -> Result<()> {
-	let text_context = TextContext::new(config, player.notes.clone(), player.variables.clone(), &player.lang, resources);
+	let text_context = player.text_context(config, resources);
 	if let Some(background) = &config.entry.background {
 		Text::print_lines_nl(background, &text_context)?;
 	}
 	if let Some(audio) = &resources.audio {
 		if let Some(sounds) = config.entry.sounds.clone() {
 			for sound in sounds {
-				audio.accept(player, &sound.into(), &text_context)?;
+				audio.accept(&player.channels, &sound.into(), &text_context)?;
 			}
 		}
 	}
@@ -20,48 +67,150 @@ pub fn first_play_init(config: &Manifest, player: &mut Player, resources: &Resou
 	Ok(())
 }
 
-pub fn begin(config: &Manifest, player: &mut Player, saves: &SaveManager, resources: &Resources, input: &mut InputController) -> Result<bool> {
+#[derive(Display, Clone, Copy)]
+/// An option offered by the [`ending_menu`].
+enum EndingOption {
+	#[strum(serialize = "Restart")]
+	Restart,
+	#[strum(serialize = "Load a save")]
+	Load,
+	#[strum(serialize = "Quit")]
+	Quit
+}
+
+/// Offers restarting, loading a save, or quitting once an ending that enables the menu is reached.
+/// 
+/// Restarting keeps the player's language, sound channels, and text speed.
+/// Loading a save also switches the file that the player data is saved to. Returns whether the game continues.
+pub fn ending_menu(config: &Manifest, player: &mut Player, saves: &SaveManager, resources: &Resources, save_file: &mut Option<PathBuf>) -> Result<bool> {
+	let mut options = vec![EndingOption::Restart];
+	if !saves.list_slots()?.is_empty() {
+		options.push(EndingOption::Load);
+	}
+	options.push(EndingOption::Quit);
+	println!();
+	let question = requestty::Question::select("The end")
+		.choices(options.iter().map(EndingOption::to_string))
+		.build();
+	let option = options[requestty::prompt_one(question)?.as_list_item().unwrap().index];
+	println!();
+	match option {
+		EndingOption::Restart => {
+			let mut restarted = Player::new(config);
+			restarted.lang = player.lang.clone();
+			restarted.channels = player.channels.clone();
+			restarted.speed = player.speed;
+			*player = restarted;
+			first_play_init(config, player, resources)?;
+		},
+		EndingOption::Load => {
+			let (loaded, file) = saves.load(config, &SaveSelection::Choose)?;
+			*player = loaded;
+			*save_file = file;
+		},
+		EndingOption::Quit => return Ok(false)
+	}
+	Ok(true)
+}
+
+/// Runs the game loop until the player quits or reaches an ending, returning whether to shut down silently.
+/// 
+/// A resumed player isn't introduced again, but the text of the prompt they resume on is always displayed,
+/// and its music starts as if the prompt was just entered.
+/// `save_file` is updated if the player loads another save from the [`ending_menu`].
+pub fn begin(config: &Manifest, player: &mut Player, saves: &SaveManager, resources: &Resources, input: &mut InputController, save_file: &mut Option<PathBuf>) -> Result<bool> {
+	// Whether the next prompt is the first since loading a save
+	let mut resumed = player.began;
 	if !player.began {
 		first_play_init(config, player, resources)?;
 	}
+	// The channel and track of the music declared by the last entered prompt
+	let mut music = None;
 	let silent = 'outer: loop {
+		if signals::shutdown_requested() {
+			break false;
+		}
+		player.record_playtime();
+		let next_prompt = Prompt::get_from_path(&resources.prompts, &player.latest_entry()?.path)?;
+		player.enter(next_prompt, config, resources)?;
 		// Text context owns variables to avoid immutable and mutable borrow overlap
-		let text_context = TextContext::new(config, player.notes.clone(), player.variables.clone(), &player.lang, resources);
+		let text_context = player.text_context(config, resources);
+		if let Some(audio) = &resources.audio {
+			music = audio.transition_music(&player.channels, music.as_ref(), next_prompt.music.as_ref(), &text_context)?;
+		}
 		let entry = player.latest_entry()?;
-		let next_prompt = Prompt::get_from_path(&resources.prompts, &entry.path)?;
 		let model = next_prompt.model(&text_context)?;
-		let choices = next_prompt.usable_choices(&player.notes, &text_context)?;
+		let choices = next_prompt.usable_choices(&text_context)?;
+		let keywords = next_prompt.keyword_choices(&text_context)?;
 
-		if choices.is_empty() {
-			return Err(anyhow!("No usable choices"))
+		if choices.is_empty() && keywords.is_empty() {
+			// Escape to the fallback prompt unless it is the one lacking choices
+			let fallback = config.settings.no_choices_fallback.as_ref()
+				.map(|path| path.fill(&entry.path, &text_context))
+				.invert()?;
+			match fallback {
+				Some(fallback) if fallback != entry.path => {
+					log::info!("No usable choices in '{}/{}'; falling back to '{}/{}'", entry.path.file, entry.path.prompt, fallback.file, fallback.prompt);
+					println!("No usable choices; returning to '{}/{}'\n", fallback.file, fallback.prompt);
+					player.jump(&fallback, config);
+					continue;
+				},
+				_ => return Err(anyhow!("No usable choices"))
+			}
 		}
 		
-		next_prompt.print(&model, entry.display, &choices, &text_context)?;
+		let display = entry.display || std::mem::take(&mut resumed);
+		next_prompt.print(&model, display, &choices, &text_context)?;
 
 		match model {
-			PromptModel::Redirect(choice) => player.choose_full(choice, None, config, resources, &model, &text_context)?,
+			PromptModel::Redirect(choice) => player.choose_full(choice, &[], config, resources, &model, &text_context)?,
 			PromptModel::Ending(lines) => {
 				Text::print_lines(lines, &text_context)?;
+				if next_prompt.choices[0].ending_menu && ending_menu(config, player, saves, resources, save_file)? {
+					resumed = true;
+					continue;
+				}
 				break 'outer true
 			},
-			_ => loop {
-				let context = next_input_context(&model, &choices, &text_context)?
-					.ok_or(anyhow!("Could not resolve input context"))?;
-				// Borrow-checker coercion; only using necessary fields in static method
-				match take_input(input, &context, config, player, saves, resources, &model, &text_context, &choices)? {
-					GameLoopResult::Retry(flush) => if flush { println!() },
-					GameLoopResult::Continue => { println!(); break },
-					GameLoopResult::Shutdown(silent) => break 'outer silent
+			_ => {
+				let mut prompt_input = PromptInput::new(&model, &text_context, &choices, &keywords);
+				loop {
+					if signals::shutdown_requested() {
+						break 'outer false;
+					}
+					let context = next_input_context(&prompt_input)?
+						.ok_or(anyhow!("Could not resolve input context"))?;
+					// Borrow-checker coercion; only using necessary fields in static method
+					match take_input(input, &context, config, player, saves, resources, &mut prompt_input)? {
+						GameLoopResult::Retry(flush) => if flush { println!() },
+						GameLoopResult::Continue => { println!(); break },
+						GameLoopResult::Shutdown(silent) => break 'outer silent,
+						GameLoopResult::EndingMenu => {
+							if ending_menu(config, player, saves, resources, save_file)? {
+								resumed = true;
+								break;
+							}
+							break 'outer true
+						}
+					}
 				}
 			}
 		}
 	};
+	player.record_playtime();
 	Ok(silent)
 }
 
-pub fn crash_context(config: &Manifest) -> String {
+/// Creates the message displayed when the game crashes, including the developers' contact information.
+/// 
+/// Contact entries are filled using a context without player data. Since the game is already crashing,
+/// an entry that fails to fill is displayed as-is rather than causing another error.
+pub fn crash_context(config: &Manifest, resources: &Resources) -> String {
 	let contact = config.metadata.contact.as_ref().map(|info| {
+		let lang = config.settings.lang.as_deref().unwrap_or("en_us");
+		let text_context = TextContext::new(config, Notes::new(), Variables::new(), BTreeSet::new(), 0, lang, resources);
 		let strings: Vec<String> = info.iter()
+			.map(|value| value.fill(&text_context).unwrap_or(value.content.clone()))
 			.map(|value| format!("- {value}"))
 			.collect();
 		format!("\n\nContact the developers:\n{}", strings.join("\n"))
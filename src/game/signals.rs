@@ -0,0 +1,56 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use anyhow::Result;
+use crossterm::terminal;
+
+/// Whether a signal has requested the game to shut down.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether a signal should request a graceful shutdown rather than exit immediately, set by [`defer`].
+static DEFERRED: AtomicBool = AtomicBool::new(false);
+
+/// The exit code used when a signal exits the game immediately, following the `128 + SIGINT` convention.
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// How often blocking waits check whether a shutdown was requested.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Installs a handler for interrupt and termination signals, such as `SIGINT` and `SIGTERM` or their Windows counterparts.
+///
+/// Until [`defer`] is called, there is no progress to save, so a signal restores the terminal and exits immediately.
+/// Afterwards, the first signal requests a graceful shutdown, checked by [`shutdown_requested`], and a second one exits without saving.
+/// Since input is read in raw mode, pressing Ctrl-C while typing is handled by the quit binding instead.
+pub fn install() -> Result<()> {
+	ctrlc::set_handler(|| {
+		if !DEFERRED.load(Ordering::Relaxed) || SHUTDOWN.swap(true, Ordering::Relaxed) {
+			restore_terminal();
+			std::process::exit(FORCED_EXIT_CODE);
+		}
+		log::info!("Received a termination signal; shutting down");
+	})?;
+	Ok(())
+}
+
+/// Defers signals to a graceful shutdown from now on, once the player has progress worth saving.
+pub fn defer() {
+	DEFERRED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a signal has requested the game to shut down.
+///
+/// The game loop checks this between prompts and between lines of text,
+/// and input is read on a separate thread while polling it, so a signal also ends any read in progress.
+pub fn shutdown_requested() -> bool {
+	SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Leaves any raw mode entered through [`crossterm`], including the one surrounding each line read by the
+/// [`InputController`](super::input::InputController), so that an interrupted read doesn't leave the terminal unusable.
+pub fn restore_terminal() {
+	let _ = terminal::disable_raw_mode();
+}
+
+/// Whether an error was caused by pressing Ctrl-C in a menu, which reads input in raw mode rather than receiving a signal.
+pub fn interrupted(err: &anyhow::Error) -> bool {
+	matches!(err.downcast_ref(), Some(requestty::ErrorKind::Interrupted))
+}
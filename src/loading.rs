@@ -1,2 +1,3 @@
+pub mod assets;
 pub mod base;
 pub mod saves;
\ No newline at end of file
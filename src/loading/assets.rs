@@ -0,0 +1,112 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Result, anyhow};
+use strum::Display;
+
+use super::base::Loader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+/// A kind of file that content references by name.
+pub enum AssetKind {
+	Sound,
+	Image
+}
+
+impl AssetKind {
+	/// The directory shared by all asset kinds.
+	pub const ASSETS_DIR: &'static str = "assets";
+
+	/// The name of the directory containing assets of this kind, such as `sounds`.
+	pub fn dir_name(&self) -> &'static str {
+		use AssetKind::*;
+		match self {
+			Sound => "sounds",
+			Image => "images"
+		}
+	}
+
+	/// The directories searched for assets of this kind, in order of precedence.
+	/// 
+	/// These are the kind's directory within the `assets` directory, such as `assets/sounds`, followed by the top-level `sounds` directory.
+	pub fn dirs(&self) -> [String; 2] {
+		[format!("{}/{}", Self::ASSETS_DIR, self.dir_name()), self.dir_name().to_owned()]
+	}
+
+	/// The error for a referenced asset of this kind that wasn't found.
+	pub fn missing(&self, name: &str) -> anyhow::Error {
+		anyhow!("Missing {self} '{name}'")
+	}
+}
+
+/// The files of a single [`AssetKind`] keyed by their content names, such as `music/theme` for `sounds/music/theme.ogg`.
+/// 
+/// Each file is stored as its path unless it was mapped to another value, such as decoded audio, by [`AssetFiles::map`].
+pub struct AssetFiles<T = PathBuf> {
+	pub kind: AssetKind,
+	pub files: BTreeMap<String, T>
+}
+
+impl AssetFiles {
+	/// Locates the files in every directory of an [`AssetKind`]. See [`AssetKind::dirs`] for the search order.
+	/// 
+	/// A name found in multiple directories resolves to the file in the first one.
+	pub fn load(loader: &Loader, kind: AssetKind) -> Result<Self> {
+		Self::map(loader, kind, Ok)
+	}
+}
+
+impl<T> AssetFiles<T> {
+	/// Locates the files of an [`AssetKind`] like [`AssetFiles::load`] and performs an operation on each file's path,
+	/// such as decoding it, with the progress reporting of [`Loader::map_content`].
+	/// 
+	/// Names are resolved first, so files shadowed by a directory of higher precedence are never mapped.
+	pub fn map<F>(loader: &Loader, kind: AssetKind, mapper: F) -> Result<Self> where T: Send, F: Fn(PathBuf) -> Result<T> + Sync {
+		let dirs: Vec<String> = kind.dirs().into_iter()
+			.filter(|dir| loader.get_path(dir).is_dir())
+			.collect();
+		if dirs.is_empty() {
+			return Ok(Self { kind, files: BTreeMap::new() });
+		}
+		let mut resolved = BTreeMap::new();
+		for dir in dirs.iter().rev() {
+			resolved.extend(loader.get_content_iterator(dir));
+		}
+		let files = loader.map_files(kind.dir_name().to_owned(), resolved.into_iter().collect(), mapper)?;
+		Ok(Self { kind, files })
+	}
+
+	/// Resolves an asset by its name.
+	pub fn get(&self, name: &str) -> Result<&T> {
+		self.files.get(name).ok_or_else(|| self.kind.missing(name))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Mutex;
+
+	use crate::loading::base::Loader;
+
+	use super::{AssetFiles, AssetKind};
+
+	#[test]
+	fn shadowed_files_are_not_mapped() {
+		let dir = std::env::temp_dir().join(format!("nage-test-assets-{}", std::process::id()));
+		for (path, content) in [("assets/sounds/theme.ogg", "assets"), ("sounds/theme.ogg", "top-level"), ("sounds/step.ogg", "top-level")] {
+			let path = dir.join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, content).unwrap();
+		}
+		let mapped = Mutex::new(Vec::new());
+		let sounds = AssetFiles::map(&Loader::new(dir.clone()), AssetKind::Sound, |path| {
+			mapped.lock().unwrap().push(path.clone());
+			Ok(std::fs::read_to_string(path)?)
+		}).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(sounds.get("theme").unwrap(), "assets");
+		assert_eq!(sounds.get("step").unwrap(), "top-level");
+		assert_eq!(sounds.get("missing").unwrap_err().to_string(), "Missing sound 'missing'");
+		assert_eq!(mapped.into_inner().unwrap().len(), 2);
+	}
+}
@@ -1,8 +1,11 @@
-use std::{path::{PathBuf, Path}, collections::BTreeMap};
+use std::{path::{PathBuf, Path}, collections::BTreeMap, cell::RefCell, io::Write, sync::atomic::{AtomicUsize, Ordering}};
 
 use anyhow::{Result, Context, anyhow};
+use crossterm::terminal::{Clear, ClearType};
 use directories::ProjectDirs;
 use format_serde_error::SerdeError;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use serde::de::DeserializeOwned;
 use walkdir::WalkDir;
 
@@ -15,14 +18,51 @@ pub type RawContents = BTreeMap<String, String>;
 
 /// Handles the loading of content and data through the file system.
 pub struct Loader {
-	pub dir: PathBuf
+	pub dir: PathBuf,
+	/// Whether to print the progress of content loading to the terminal.
+	pub progress: bool,
+	/// The amount of files loaded from each content directory.
+	counts: RefCell<BTreeMap<String, usize>>
 }
 
 impl Loader {
 	/// Constructs a loader from a base directory.
 	/// Any input paths will be inside this directory.
 	pub fn new(dir: PathBuf) -> Self {
-		Loader { dir }
+		Loader { 
+			dir,
+			progress: false,
+			counts: RefCell::new(BTreeMap::new())
+		}
+	}
+
+	/// Returns the amount of files loaded from each content directory so far.
+	pub fn file_counts(&self) -> BTreeMap<String, usize> {
+		self.counts.borrow().clone()
+	}
+
+	/// Formats [`Loader::file_counts`] into a single line, such as `Loaded files: info (1), prompts (12)`.
+	pub fn summary(&self) -> String {
+		let counts: Vec<String> = self.file_counts().iter()
+			.map(|(name, count)| format!("{name} ({count})"))
+			.collect();
+		format!("Loaded files: {}", counts.join(", "))
+	}
+
+	/// If progress reporting is enabled, overwrites the current terminal line with the loading status of a content directory.
+	fn report_progress(progress: bool, name: &str, current: usize, total: usize) {
+		if progress {
+			print!("\r{}Loading {name}... ({current}/{total})", Clear(ClearType::CurrentLine));
+			let _ = std::io::stdout().flush();
+		}
+	}
+
+	/// If progress reporting is enabled, clears the last loading status line.
+	pub fn finish_progress(&self) {
+		if self.progress {
+			print!("\r{}", Clear(ClearType::CurrentLine));
+			let _ = std::io::stdout().flush();
+		}
 	}
 
 	pub fn config_dir() -> Result<PathBuf> {
@@ -32,21 +72,69 @@ impl Loader {
 	}
 
 	/// Gets the path relative to the inside of the base directory.
-	fn get_path<P>(&self, path: P) -> PathBuf where P: AsRef<Path> {
+	pub fn get_path<P>(&self, path: P) -> PathBuf where P: AsRef<Path> {
 		self.dir.join(path)
 	}
 
 	/// Parses some [`String`] content into a deserializable type.
-	fn parse<T>(content: String) -> Result<T> where T: DeserializeOwned {
+	pub fn parse<T>(content: String) -> Result<T> where T: DeserializeOwned {
 		let parsed = serde_yaml::from_str::<T>(&content)
 			.map_err(|err| SerdeError::new(content.clone(), err))?;
 		Ok(parsed)
 	}
 
+	/// Removes `//` line comments and `/* */` block comments from JSON content, leaving string literals intact.
+	/// 
+	/// Comments are replaced with whitespace and their newlines are kept so that parse errors point to the original lines.
+	fn strip_json_comments(content: &str) -> String {
+		let mut result = String::with_capacity(content.len());
+		let mut chars = content.chars().peekable();
+		let mut in_string = false;
+		while let Some(c) = chars.next() {
+			if in_string {
+				result.push(c);
+				match c {
+					'\\' => if let Some(escaped) = chars.next() { result.push(escaped) },
+					'"' => in_string = false,
+					_ => {}
+				}
+				continue;
+			}
+			match (c, chars.peek()) {
+				('/', Some('/')) => {
+					while chars.next_if(|&next| next != '\n').is_some() {}
+				},
+				('/', Some('*')) => {
+					chars.next();
+					result.push_str("  ");
+					let mut previous = None;
+					for next in chars.by_ref() {
+						if previous == Some('*') && next == '/' {
+							break;
+						}
+						result.push(if next == '\n' { next } else { ' ' });
+						previous = Some(next);
+					}
+					result.push(' ');
+				},
+				_ => {
+					in_string = c == '"';
+					result.push(c);
+				}
+			}
+		}
+		result
+	}
+
 	/// Reads a file given a path and deserializes it into the specified type.
+	/// 
+	/// Files with a `.json` extension may contain `//` and `/* */` comments, which are stripped before parsing.
 	pub fn load<P, T>(path: P) -> Result<T> where P: AsRef<Path>, T: DeserializeOwned {
-		let content = std::fs::read_to_string(&path)
+		let mut content = std::fs::read_to_string(&path)
     		.with_context(|| format!("{} doesn't exist", path.as_ref().display()))?;
+		if path.as_ref().extension().is_some_and(|extension| extension == "json") {
+			content = Self::strip_json_comments(&content);
+		}
 		Self::parse(content)
     		.with_context(|| format!("Failed to parse {}", path.as_ref().display()))
 	}
@@ -55,7 +143,7 @@ impl Loader {
 	/// 
 	/// Each entry in the iterator is a tuple of the actual path and the file "key", 
 	/// wherein the key is formatted as `relative/dir/file_name`, without the preceding input directory and file extension.
-	fn get_content_iterator<P>(&self, path: P) -> impl Iterator<Item = (String, PathBuf)> where P: AsRef<Path> {
+	pub fn get_content_iterator<P>(&self, path: P) -> impl Iterator<Item = (String, PathBuf)> where P: AsRef<Path> {
 		let full = self.get_path(path);
 		let prefix = format!("{}/", full.to_str().unwrap());
 		WalkDir::new(full)
@@ -71,10 +159,36 @@ impl Loader {
 
 	/// Iterates over content files, performs the specified operation on the file path, 
 	/// and combines the results into an ordered [`BTreeMap`].
-	pub fn map_content<P, T, F>(&self, path: P, mapper: F) -> Result<BTreeMap<String, T>> where P: AsRef<Path>, F: Fn(PathBuf) -> Result<T> {
-		self.get_content_iterator(path)
-    		.map(|(key, path)| Ok((key, mapper(path)?)))
-    		.collect()
+	/// 
+	/// With the `parallel` feature enabled, files are mapped across multiple threads.
+	/// Either way, if any files fail, the error of the first such file in key order is returned.
+	/// 
+	/// Progress is reported per file and the total amount of files is recorded in [`Loader::file_counts`].
+	pub fn map_content<P, T, F>(&self, path: P, mapper: F) -> Result<BTreeMap<String, T>> where P: AsRef<Path>, T: Send, F: Fn(PathBuf) -> Result<T> + Sync {
+		let name = path.as_ref().display().to_string();
+		let files: Vec<(String, PathBuf)> = self.get_content_iterator(&path).collect();
+		self.map_files(name, files, mapper)
+	}
+
+	/// Performs an operation on files that were already resolved to their keys, recorded under `name`.
+	/// See [`Loader::map_content`] for progress reporting and parallelism.
+	pub fn map_files<T, F>(&self, name: String, files: Vec<(String, PathBuf)>, mapper: F) -> Result<BTreeMap<String, T>> where T: Send, F: Fn(PathBuf) -> Result<T> + Sync {
+		let total = files.len();
+		log::debug!("Loading {total} files from '{name}'");
+		let progress = self.progress;
+		let finished = AtomicUsize::new(0);
+		let map_file = |(key, path): (String, PathBuf)| {
+			let result = mapper(path).map(|value| (key, value));
+			Self::report_progress(progress, &name, finished.fetch_add(1, Ordering::Relaxed) + 1, total);
+			result
+		};
+		#[cfg(feature = "parallel")]
+		let results: Vec<Result<(String, T)>> = files.into_par_iter().map(map_file).collect();
+		#[cfg(not(feature = "parallel"))]
+		let results: Vec<Result<(String, T)>> = files.into_iter().map(map_file).collect();
+		let result = results.into_iter().collect::<Result<BTreeMap<String, T>>>()?;
+		self.counts.borrow_mut().insert(name, total);
+		Ok(result)
 	}
 
 	/// Iterates over content files, reads them, and combines their content into a [`String`] map.
@@ -83,7 +197,7 @@ impl Loader {
 	}
 
 	/// Iterates over content files, deserializes their content, and combines them into a [`Contents`] map.
-	pub fn load_content<P, T>(&self, path: P) -> Result<Contents<T>> where P: AsRef<Path>, T: DeserializeOwned {
+	pub fn load_content<P, T>(&self, path: P) -> Result<Contents<T>> where P: AsRef<Path>, T: DeserializeOwned + Send {
 		self.map_content(path, |local| Ok(Self::load(local)?))
 	}
 
@@ -93,3 +207,41 @@ impl Loader {
 	}
 }
 
+
+#[cfg(test)]
+mod benches {
+	use test::Bencher;
+
+	use crate::core::prompt::Prompt;
+
+	use super::Loader;
+
+	/// The amount of prompt files in the generated story.
+	const FILES: usize = 200;
+	/// The amount of prompts within each generated file.
+	const PROMPTS: usize = 20;
+
+	/// Writes a story of [`FILES`] prompt files to a temporary directory and returns a loader over it.
+	fn story() -> Loader {
+		let dir = std::env::temp_dir().join(format!("nage-bench-{}", std::process::id()));
+		let prompts = dir.join("prompts");
+		std::fs::create_dir_all(&prompts).unwrap();
+		let file: String = (0..PROMPTS)
+			.map(|index| format!("prompt_{index}:\n  prompt:\n  - text: You stand at crossing {index}.\n  choices:\n  - response:\n      text: Go onwards\n    jump:\n      prompt: prompt_{}\n", (index + 1) % PROMPTS))
+			.collect();
+		for index in 0..FILES {
+			std::fs::write(prompts.join(format!("file_{index}.yml")), &file).unwrap();
+		}
+		Loader::new(dir)
+	}
+
+	/// Loads the generated story with whichever path the `parallel` feature selects.
+	/// 
+	/// Compare `cargo bench load_many_files` with `cargo bench --features parallel load_many_files`.
+	#[bench]
+	fn load_many_files(b: &mut Bencher) {
+		let loader = story();
+		b.iter(|| loader.load_content::<_, Prompt>("prompts").unwrap());
+		std::fs::remove_dir_all(&loader.dir).unwrap();
+	}
+}
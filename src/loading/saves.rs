@@ -1,21 +1,210 @@
-use std::{path::{PathBuf, Path}, ffi::OsStr};
+use std::{path::{PathBuf, Path}, ffi::OsStr, time::SystemTime};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, Context};
+use semver::Version;
+use serde::{Deserialize, Serialize};
 
-use crate::core::{player::Player, manifest::Manifest};
+use crate::{core::{player::Player, manifest::Manifest, prompt::Prompt, resources::Resources}, game::signals};
 
 use super::base::Loader;
 
 pub struct SaveManager {
-	dir: PathBuf
+	dir: PathBuf,
+	/// See [`Settings::max_save_size`](crate::core::manifest::Settings::max_save_size).
+	max_size: Option<usize>
+}
+
+/// A summary of an existing save file, displayed when choosing which save to load.
+pub struct SaveSlot {
+	/// The path of the save file.
+	pub file: PathBuf,
+	/// The file and name of the prompt the player is on, if the save could be read.
+	pub location: Option<String>,
+	/// When the save file was last written to, if known.
+	pub modified: Option<SystemTime>
+}
+
+impl SaveSlot {
+	/// The name of the save file without its extension.
+	pub fn name(&self) -> &str {
+		self.file.file_stem().and_then(OsStr::to_str).unwrap_or_default()
+	}
+
+	/// Formats how long ago the save was last written to, e.g. `5 minutes ago`.
+	pub fn age(&self) -> Option<String> {
+		let seconds = self.modified?.elapsed().ok()?.as_secs();
+		let (amount, unit) = match seconds {
+			0..=59 => return Some("just now".to_owned()),
+			60..=3599 => (seconds / 60, "minute"),
+			3600..=86399 => (seconds / 3600, "hour"),
+			_ => (seconds / 86400, "day")
+		};
+		let plural = if amount == 1 { "" } else { "s" };
+		Some(format!("{amount} {unit}{plural} ago"))
+	}
+
+	/// The text displayed for this slot in the save selection menu.
+	fn label(&self) -> String {
+		let location = self.location.as_deref().unwrap_or("unreadable");
+		match self.age() {
+			Some(age) => format!("{} ({location}, saved {age})", self.name()),
+			None => format!("{} ({location})", self.name())
+		}
+	}
+}
+
+/// How the save to play is selected when the game starts.
+pub enum SaveSelection {
+	/// Starts a new game.
+	New,
+	/// Loads the save file of a specific name.
+	Named(String),
+	/// Continues from the last written save without any menus, or the most recently modified one if it is missing.
+	Continue,
+	/// Chooses from the existing saves if there are multiple, defaulting to the last written save.
+	Choose
+}
+
+#[derive(Deserialize, Serialize)]
+/// A self-contained save written as JSON, used to move saves between machines.
+/// 
+/// The player data is borrowed when exporting and owned when importing.
+pub struct SaveBundle<P> {
+	/// The version of the bundle format, checked against [`SaveBundle::FORMAT`] on import.
+	pub format: u32,
+	/// The ID of the game the save belongs to.
+	pub game: String,
+	/// The version of the game the save was exported from.
+	pub version: Version,
+	/// The exported player data.
+	pub player: P
+}
+
+impl SaveBundle<Player> {
+	/// The current version of the bundle format.
+	pub const FORMAT: u32 = 1;
+
+	/// Writes the player data to a bundle file tagged with the game's ID and version.
+	pub fn export(config: &Manifest, player: &Player, file: &Path) -> Result<()> {
+		let bundle = SaveBundle {
+			format: Self::FORMAT,
+			game: config.metadata.game_id().to_owned(),
+			version: config.metadata.version.clone(),
+			player
+		};
+		let content = serde_json::to_string_pretty(&bundle)?;
+		std::fs::write(file, content)
+			.with_context(|| format!("Failed to write save bundle '{}'", file.display()))
+	}
+
+	/// Reads the player data from a bundle file.
+	/// 
+	/// Fails if the bundle was exported from a different game, from a newer version of the game, or uses a different bundle format.
+	/// Since the prompts of older versions may have been removed, every prompt in the imported history must also exist.
+	pub fn import(config: &Manifest, resources: &Resources, file: &Path) -> Result<Player> {
+		let content = std::fs::read_to_string(file)
+			.with_context(|| format!("{} doesn't exist", file.display()))?;
+		let bundle: Self = serde_json::from_str(&content)
+			.with_context(|| format!("'{}' is not a valid save bundle", file.display()))?;
+		if bundle.format != Self::FORMAT {
+			return Err(anyhow!("Unsupported save bundle format {} (expected {})", bundle.format, Self::FORMAT));
+		}
+		let game_id = config.metadata.game_id();
+		if bundle.game != game_id {
+			return Err(anyhow!("Save bundle belongs to the game '{}', not '{game_id}'", bundle.game));
+		}
+		let game_version = &config.metadata.version;
+		if bundle.version > *game_version {
+			return Err(anyhow!("Save bundle is from version '{}' of the game, which is newer than '{game_version}'", bundle.version));
+		}
+		if bundle.version != *game_version {
+			log::warn!("Importing save bundle from version '{}' of the game into '{game_version}'", bundle.version);
+		}
+		for entry in &bundle.player.history {
+			Prompt::get_from_path(&resources.prompts, &entry.path)
+				.with_context(|| format!("Save bundle refers to prompt '{}/{}', which doesn't exist in this version", entry.path.file, entry.path.prompt))?;
+		}
+		Ok(bundle.player)
+	}
 }
 
 impl SaveManager {
-	pub fn dir(config: &Manifest, create: bool) -> Result<PathBuf> {
-		let dir = Loader::config_dir()?
-    		.join("games")
-			.join(config.metadata.game_id())
-			.join("saves");
+	/// The name displayed for the default profile in selection menus.
+	pub const DEFAULT_PROFILE: &'static str = "(default)";
+
+	/// The data directory of a game, containing the default profile's saves and any named profiles.
+	fn game_dir(config: &Manifest) -> Result<PathBuf> {
+		Ok(Loader::config_dir()?
+			.join("games")
+			.join(config.metadata.game_id()))
+	}
+
+	/// The directory containing all named profiles of a game.
+	fn profiles_dir(config: &Manifest) -> Result<PathBuf> {
+		Ok(Self::game_dir(config)?.join("profiles"))
+	}
+
+	/// Lists the names of a game's existing named profiles.
+	pub fn profiles(config: &Manifest) -> Result<Vec<String>> {
+		let dir = Self::profiles_dir(config)?;
+		if !dir.exists() {
+			return Ok(Vec::new());
+		}
+		let mut result: Vec<String> = std::fs::read_dir(dir)?
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| entry.path().is_dir())
+			.filter_map(|entry| entry.file_name().to_str().map(|name| name.to_owned()))
+			.collect();
+		result.sort();
+		Ok(result)
+	}
+
+	/// Whether the game hasn't been played yet, meaning there are no named profiles and no saves in the default profile.
+	pub fn is_first_run(config: &Manifest) -> Result<bool> {
+		if !Self::profiles(config)?.is_empty() {
+			return Ok(false);
+		}
+		let result = match Self::dir(config, None, false) {
+			Ok(dir) => Self { dir, max_size: None }.saves()?.is_empty(),
+			Err(_) => true
+		};
+		Ok(result)
+	}
+
+	/// Prompts the player to select a profile if any named profiles exist.
+	/// 
+	/// Returns [`None`] for the default profile.
+	pub fn choose_profile(config: &Manifest) -> Result<Option<String>> {
+		let profiles = Self::profiles(config)?;
+		if profiles.is_empty() {
+			return Ok(None);
+		}
+		let choices: Vec<&str> = std::iter::once(Self::DEFAULT_PROFILE)
+			.chain(profiles.iter().map(|name| name.as_str()))
+			.collect();
+		let prompt = requestty::Question::select("Choose a profile")
+			.choices(choices)
+			.build();
+		let choice = requestty::prompt_one(prompt)?.as_list_item().unwrap().index;
+
+		println!();
+
+		Ok(choice.checked_sub(1).map(|index| profiles[index].clone()))
+	}
+
+	/// The save directory of a profile. The default profile, [`None`], stores saves directly under the game directory,
+	/// while named profiles store them under `profiles/<name>`.
+	pub fn dir(config: &Manifest, profile: Option<&str>, create: bool) -> Result<PathBuf> {
+		let base = match profile {
+			None => Self::game_dir(config)?,
+			Some(name) => {
+				if !sanitize_filename::is_sanitized(name) {
+					return Err(anyhow!("Invalid profile name '{name}'"));
+				}
+				Self::profiles_dir(config)?.join(name)
+			}
+		};
+		let dir = base.join("saves");
 		if !dir.exists() {
 			if create {
 				std::fs::create_dir_all(&dir)?;
@@ -27,9 +216,10 @@ impl SaveManager {
 		Ok(dir)
 	}
 
-	pub fn new(config: &Manifest) -> Result<Self> {
+	pub fn new(config: &Manifest, profile: Option<&str>) -> Result<Self> {
 		let saves = Self { 
-			dir: SaveManager::dir(config, true)?
+			dir: SaveManager::dir(config, profile, true)?,
+			max_size: config.settings.max_save_size
 		};
 		Ok(saves)
 	}
@@ -43,15 +233,56 @@ impl SaveManager {
     		.map_err(|err| anyhow!(err))
 	}
 
-	fn load_player<P>(&self, file: P) -> Result<Player> where P: AsRef<Path> {
-		Loader::load(self.dir.join(file))
+	/// Loads a player from a save file.
+	/// 
+	/// If the file exists but can't be parsed, recovers using [`SaveManager::recover`].
+	fn load_player<P>(&self, config: &Manifest, file: P) -> Result<(Player, Option<PathBuf>)> where P: AsRef<Path> {
+		let path = self.dir.join(&file);
+		log::info!("Loading save file '{}'", path.display());
+		let content = std::fs::read_to_string(&path)
+			.with_context(|| format!("{} doesn't exist", path.display()))?;
+		match Loader::parse(content) {
+			Ok(player) => Ok((player, Some(file.as_ref().to_path_buf()))),
+			Err(err) => Self::recover(config, &path, err)
+		}
 	}
-	
-	fn load_last_save(&self) -> Result<(Player, PathBuf)> {
-		let file = self.last_save_file()?;
-		Ok((self.load_player(file.clone())?, PathBuf::from(file)))
+
+	/// Handles a corrupted save file by explaining what failed to parse and offering to start a new game instead.
+	/// 
+	/// The file is only backed up once the player agrees, using the first free name from [`SaveManager::backup_path`].
+	/// Otherwise, it is left in place.
+	fn recover(config: &Manifest, path: &Path, err: anyhow::Error) -> Result<(Player, Option<PathBuf>)> {
+		log::warn!("Failed to parse save file '{}': {err:?}", path.display());
+		println!("The save file '{}' is corrupted and couldn't be loaded:\n{err}\n", path.display());
+		let question = requestty::Question::confirm("fresh")
+			.message("Start a new game?")
+			.default(true)
+			.build();
+		if !requestty::prompt_one(question)?.as_bool().unwrap() {
+			return Err(anyhow!("Save file '{}' is corrupted", path.display()));
+		}
+		let backup = Self::backup_path(path);
+		std::fs::rename(path, &backup)
+			.with_context(|| format!("Failed to back up corrupted save file '{}'", path.display()))?;
+		println!("The corrupted save file has been backed up to '{}'\n", backup.display());
+		Ok((Player::new(config), None))
 	}
 
+	/// The first of `<file>.bak`, `<file>.2.bak`, and so on that doesn't exist yet, so that earlier backups are kept.
+	fn backup_path(path: &Path) -> PathBuf {
+		(1..)
+			.map(|index| {
+				let mut backup = path.as_os_str().to_owned();
+				match index {
+					1 => backup.push(".bak"),
+					_ => backup.push(format!(".{index}.bak"))
+				}
+				PathBuf::from(backup)
+			})
+			.find(|backup| !backup.exists())
+			.unwrap()
+	}
+	
 	fn saves(&self) -> Result<Vec<PathBuf>> {
 		let result = std::fs::read_dir(&self.dir)?
 			.filter_map(|entry| entry.ok())
@@ -61,31 +292,78 @@ impl SaveManager {
 		Ok(result)
 	}
 
-	fn choose_save(saves: &Vec<PathBuf>) -> Result<PathBuf> {
-		let save_names: Vec<&str> = saves.iter()
-    		.map(|save| save.file_stem().and_then(OsStr::to_str).unwrap())
+	/// Lists the existing save files along with where each player is, most recently saved first.
+	pub fn list_slots(&self) -> Result<Vec<SaveSlot>> {
+		let mut slots: Vec<SaveSlot> = self.saves()?.into_iter()
+			.map(|file| {
+				let location = std::fs::read_to_string(&file).ok()
+					.and_then(|content| Loader::parse::<Player>(content).ok())
+					.and_then(|player| player.latest_entry().ok().map(|entry| format!("{}/{}", entry.path.file, entry.path.prompt)));
+				let modified = std::fs::metadata(&file).and_then(|meta| meta.modified()).ok();
+				SaveSlot { file, location, modified }
+			})
+			.collect();
+		slots.sort_by_key(|slot| std::cmp::Reverse(slot.modified));
+		Ok(slots)
+	}
+
+	/// The name displayed for starting a new game in the save selection menu.
+	const NEW_GAME: &'static str = "(new game)";
+
+	/// The index of the last written save among the slots, falling back to the most recently modified slot.
+	fn last_slot(&self, slots: &[SaveSlot]) -> usize {
+		let last = self.last_save_file().ok();
+		slots.iter()
+			.position(|slot| last.as_deref().map(|file| slot.file.ends_with(file)).unwrap_or(false))
+			.unwrap_or_default()
+	}
+
+	/// Prompts the player to select a save slot, defaulting to the last used save.
+	/// 
+	/// Returns [`None`] if the player chooses to start a new game.
+	fn choose_slot(&self, slots: &[SaveSlot]) -> Result<Option<PathBuf>> {
+		let default = self.last_slot(slots);
+		let choices: Vec<String> = slots.iter()
+			.map(SaveSlot::label)
+			.chain(std::iter::once(Self::NEW_GAME.to_owned()))
 			.collect();
 		let prompt = requestty::Question::select("Choose a save file")
-    		.choices(save_names)
+			.choices(choices)
+			.default(default)
 			.build();
 		let choice = requestty::prompt_one(prompt)?.as_list_item().unwrap().index;
 
 		println!();
 
-		Ok(saves[choice].clone())
+		Ok(slots.get(choice).map(|slot| slot.file.clone()))
 	}
 
-	pub fn load(&self, config: &Manifest, pick: bool, new: bool) -> Result<(Player, Option<PathBuf>)> {
-		let saves = self.saves()?;
-		if new || saves.is_empty() {
-			return Ok((Player::new(config), None));
-		}
-		if pick {
-			let save = Self::choose_save(&saves)?;
-			Ok((self.load_player(&save)?, Some(save)))
-		}
-		else {
-			self.load_last_save().map(|(player, path)| (player, Some(path)))
+	/// Loads the player data to play with according to a [`SaveSelection`].
+	/// 
+	/// A new game is started if there are no existing saves to continue or choose from.
+	pub fn load(&self, config: &Manifest, selection: &SaveSelection) -> Result<(Player, Option<PathBuf>)> {
+		use SaveSelection::*;
+		let slots = match selection {
+			New => return Ok((Player::new(config), None)),
+			Named(name) => {
+				let file = if name.ends_with(".yml") { name.to_owned() } else { format!("{name}.yml") };
+				return self.load_player(config, file);
+			},
+			Continue | Choose => self.list_slots()?
+		};
+		let file = match (selection, slots.len()) {
+			(_, 0) => None,
+			(Choose, 1) => Some(slots[0].file.clone()),
+			(Choose, _) => self.choose_slot(&slots)?,
+			_ => {
+				let slot = &slots[self.last_slot(&slots)];
+				println!("Continuing from {}; run with `--choose` to pick another save\n", slot.label());
+				Some(slot.file.clone())
+			}
+		};
+		match file {
+			Some(file) => self.load_player(config, file),
+			None => Ok((Player::new(config), None))
 		}
 	}
 
@@ -103,21 +381,196 @@ impl SaveManager {
 		Ok(format!("{}.yml", answer.as_string().unwrap()))
 	}
 	
-	fn write_player(&self, save_file: &PathBuf, player: &Player) {
-		if let Ok(content) = serde_yaml::to_string(player) {
-			let _ = std::fs::write(self.dir.join(&save_file), content);
-		}	
+	/// The serialized size of the entry that [`SaveManager::evict_oldest`] would remove, or `None` if nothing can be removed.
+	fn oldest_entry_size(player: &Player) -> Result<Option<usize>> {
+		let size = match (player.journal.first(), player.history.front()) {
+			(Some(entry), _) => serde_yaml::to_string(entry)?.len(),
+			(None, Some(entry)) if player.history.len() > 1 => serde_yaml::to_string(entry)?.len(),
+			_ => return Ok(None)
+		};
+		Ok(Some(size))
 	}
 
-	pub fn write(&self, player: &Player, save_file: Option<PathBuf>, new: bool) -> Result<()> {
-		let save = if new || save_file.is_none() {
-			PathBuf::from(Self::prompt_new_save_file()?)
+	/// Removes the player's oldest journal entry or, once the journal is empty, the oldest history entry.
+	/// The current history entry is always kept.
+	fn evict_oldest(player: &mut Player) {
+		if !player.journal.is_empty() {
+			player.journal.remove(0);
 		}
-		else {
-			save_file.unwrap()
+		else if player.history.len() > 1 {
+			player.history.pop_front();
+		}
+	}
+
+	/// Serializes the player, evicting old entries until the content fits within the maximum save size.
+	/// 
+	/// Evicted entries are removed from the player itself so that later saves stay consistent.
+	/// If the content still doesn't fit once nothing else can be removed, it is written anyway.
+	fn serialize_player(&self, player: &mut Player) -> Result<String> {
+		let mut content = serde_yaml::to_string(&*player)?;
+		let Some(max_size) = self.max_size else {
+			return Ok(content);
+		};
+		let (journal, history) = (player.journal.len(), player.history.len());
+		while content.len() > max_size {
+			// Estimate how much to remove from the entry sizes to avoid serializing after every removal,
+			// serializing again before the removal that would cover the excess so that no more is evicted than needed
+			let mut excess = content.len() - max_size;
+			let mut evicted = false;
+			while let Some(size) = Self::oldest_entry_size(player)? {
+				if evicted && size >= excess {
+					break;
+				}
+				Self::evict_oldest(player);
+				excess = excess.saturating_sub(size);
+				evicted = true;
+			}
+			if !evicted {
+				log::warn!("Save data is {} bytes, exceeding the maximum of {max_size}, with nothing left to evict", content.len());
+				break;
+			}
+			content = serde_yaml::to_string(&*player)?;
+		}
+		let (journal, history) = (journal - player.journal.len(), history - player.history.len());
+		if journal > 0 || history > 0 {
+			log::warn!("Evicted {journal} journal and {history} history entries to fit the maximum save size of {max_size} bytes");
+		}
+		Ok(content)
+	}
+
+	fn write_player(&self, save_file: &PathBuf, player: &mut Player) {
+		let path = self.dir.join(save_file);
+		let result = self.serialize_player(player)
+			.and_then(|content| Ok(std::fs::write(&path, content)?));
+		match result {
+			Ok(()) => log::info!("Saved player data to '{}'", path.display()),
+			Err(err) => log::error!("Failed to save player data to '{}': {err}", path.display())
+		}
+	}
+
+	/// The file name that [`SaveManager::autosave_file`] numbers to avoid overwriting existing saves.
+	const AUTOSAVE_NAME: &'static str = "autosave";
+
+	/// The first of `autosave.yml`, `autosave-2.yml`, and so on that doesn't exist yet.
+	fn autosave_file(&self) -> PathBuf {
+		(1..)
+			.map(|index| match index {
+				1 => format!("{}.yml", Self::AUTOSAVE_NAME),
+				_ => format!("{}-{index}.yml", Self::AUTOSAVE_NAME)
+			})
+			.map(PathBuf::from)
+			.find(|file| !self.dir.join(file).exists())
+			.unwrap()
+	}
+
+	/// Writes the player to its save file, asking for a new file name if the player is new or lacks one.
+	/// 
+	/// After a signal requests a shutdown, nobody may be there to answer, so an [`autosave_file`](SaveManager::autosave_file) is used instead.
+	pub fn write(&self, player: &mut Player, save_file: Option<PathBuf>, new: bool) -> Result<()> {
+		let save = match save_file {
+			Some(file) if !new => file,
+			_ if signals::shutdown_requested() => self.autosave_file(),
+			_ => PathBuf::from(Self::prompt_new_save_file()?)
 		};
 		self.write_player(&save, player);
 		let _ = std::fs::write(self.save_name_storage(), save.to_str().unwrap());
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::VecDeque;
+
+	use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+	use strum::IntoEnumIterator;
+
+	use crate::{core::{choice::VariableList, manifest::Manifest, player::{HistoryEntry, LogEntry, NoteEntry, PathEntry, Player, VariableEntry}}, loading::base::Loader, text::display::PlayerTextSpeed};
+
+	use super::SaveBundle;
+
+	/// The amount of random players checked by each property.
+	const CASES: u64 = 256;
+
+	/// Strings that YAML would read as something other than a string if written unquoted.
+	const SPECIAL: [&str; 12] = ["", "~", "null", "true", "no", "1.0", "0x1F", "- item", "key: value", "# comment", "'quoted'", " padded "];
+
+	/// Generates a string that is either special to YAML or random characters, including non-ASCII and control characters.
+	fn string(rng: &mut StdRng) -> String {
+		if rng.gen_bool(0.2) {
+			return SPECIAL.choose(rng).unwrap().to_string();
+		}
+		const CHARS: [char; 16] = ['a', 'Z', '0', ' ', '"', '\'', '\\', ':', '#', '\n', '\t', '\u{1E}', 'é', '\u{301}', '世', '🦀'];
+		(0..rng.gen_range(0..12))
+			.map(|_| *CHARS.choose(rng).unwrap())
+			.collect()
+	}
+
+	fn strings<T: FromIterator<String>>(rng: &mut StdRng) -> T {
+		(0..rng.gen_range(0..5)).map(|_| string(rng)).collect()
+	}
+
+	/// Generates a variable value, which may be a list.
+	fn variable(rng: &mut StdRng) -> String {
+		if rng.gen_bool(0.3) { VariableList(strings(rng)).serialize() } else { string(rng) }
+	}
+
+	fn history_entry(rng: &mut StdRng) -> HistoryEntry {
+		HistoryEntry {
+			path: PathEntry { file: string(rng), prompt: string(rng) },
+			display: rng.gen(),
+			locked: rng.gen(),
+			redirect: rng.gen(),
+			notes: rng.gen_bool(0.5).then(|| (0..rng.gen_range(0..3)).map(|_| NoteEntry { value: string(rng), take: rng.gen() }).collect()),
+			variables: rng.gen_bool(0.5).then(|| {
+				(0..rng.gen_range(0..3))
+					.map(|_| (string(rng), VariableEntry { value: variable(rng), previous: rng.gen_bool(0.5).then(|| variable(rng)) }))
+					.collect()
+			}),
+			log: rng.gen()
+		}
+	}
+
+	/// Generates a player with random data in every persisted field.
+	fn player(rng: &mut StdRng, config: &Manifest) -> Player {
+		let mut player = Player::new(config);
+		player.began = rng.gen();
+		player.lang = string(rng);
+		player.channels = strings(rng);
+		player.speed = *PlayerTextSpeed::iter().collect::<Vec<_>>().choose(rng).unwrap();
+		player.notes = strings(rng);
+		player.variables = (0..rng.gen_range(0..5)).map(|_| (string(rng), variable(rng))).collect();
+		player.info_pages = strings(rng);
+		player.log = strings(rng);
+		player.journal = (0..rng.gen_range(0..4)).map(|_| LogEntry { text: string(rng), playtime: rng.gen() }).collect();
+		player.history = (0..rng.gen_range(1..5)).map(|_| history_entry(rng)).collect::<VecDeque<_>>();
+		player.playtime = rng.gen();
+		player
+	}
+
+	/// Checks that a property holds for [`CASES`] random players, reporting the seed of the first failure.
+	fn check<F>(property: F) where F: Fn(&Player, &Manifest) -> bool {
+		let config = Manifest::minimal();
+		for seed in 0..CASES {
+			let player = player(&mut StdRng::seed_from_u64(seed), &config);
+			assert!(property(&player, &config), "property failed for seed {seed}: {player:#?}");
+		}
+	}
+
+	#[test]
+	fn save_round_trip() {
+		check(|player, _| {
+			let content = serde_yaml::to_string(player).unwrap();
+			Loader::parse::<Player>(content).unwrap() == *player
+		});
+	}
+
+	#[test]
+	fn bundle_round_trip() {
+		check(|player, config| {
+			let bundle = SaveBundle { format: SaveBundle::FORMAT, game: config.metadata.game_id().to_owned(), version: config.metadata.version.clone(), player };
+			let content = serde_json::to_string(&bundle).unwrap();
+			serde_json::from_str::<SaveBundle<Player>>(&content).unwrap().player == *player
+		});
+	}
+}
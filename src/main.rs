@@ -1,15 +1,16 @@
 #![feature(result_flattening)]
 #![feature(iterator_try_collect)]
+#![cfg_attr(test, feature(test))]
 
-use std::path::PathBuf;
+use std::{path::PathBuf, io::IsTerminal};
 
 use crate::core::{manifest::Manifest, resources::Resources};
 
 use anyhow::{Result, Context};
 use clap::Parser;
-use cmd::cli::CliCommand;
-use game::{main::{begin, crash_context}, input::InputController};
-use loading::{base::Loader, saves::SaveManager};
+use cmd::{cli::CliCommand, logging};
+use game::{main::{begin, crash_context, first_run_menu, FirstRunSetup}, input::InputController, signals};
+use loading::{base::Loader, saves::{SaveManager, SaveSelection}};
 
 mod core;
 mod game;
@@ -17,30 +18,70 @@ mod cmd;
 mod loading;
 mod text;
 
+#[cfg(test)]
+extern crate test;
+
 pub const NAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-fn run(path: PathBuf, pick: bool, new: bool) -> Result<()> {
-    // Create content loader
-    let loader = Loader::new(path);
+fn run(path: PathBuf, selection: SaveSelection, profile: Option<String>, no_setup: bool, no_audio: bool, instant: bool) -> Result<()> {
+    // Handle interrupt and termination signals, exiting immediately until there is progress to save
+    signals::install()?;
+    // Create content loader, reporting progress only to interactive terminals
+    let mut loader = Loader::new(path);
+    loader.progress = std::io::stdout().is_terminal();
     // Load content and data
-    let config = Manifest::load(&loader)?;
+    let mut config = Manifest::load(&loader)?;
+    if no_audio {
+        config.settings.audio = false;
+    }
+    if instant {
+        config.settings.instant_text = true;
+    }
+    if config.settings.debug {
+        logging::enable_debug();
+    }
     let resources = Resources::load(&loader, &config)?;
-    // Load player
-    let saves = SaveManager::new(&config)?;
-    let (mut player, save_file) = saves.load(&config, pick, new)?;
+    log::info!("{}", loader.summary());
+    let menus = || -> Result<_> {
+        // Ask for a language and profile on first run
+        let setup = if no_setup { FirstRunSetup::default() } else { first_run_menu(&config, &resources)? };
+        // Select profile and load player
+        let profile = match profile.or(setup.profile) {
+            Some(name) => Some(name),
+            None => SaveManager::choose_profile(&config)?
+        };
+        let saves = SaveManager::new(&config, profile.as_deref())?;
+        let (player, save_file) = saves.load(&config, &selection)?;
+        Ok((setup.lang, saves, player, save_file))
+    };
+    // Pressing Ctrl-C in a menu quits before there is any progress to save
+    let (lang, saves, mut player, mut save_file) = match menus() {
+        Err(err) if signals::interrupted(&err) => return Ok(()),
+        result => result?
+    };
+    // Shut down gracefully on the first interrupt or termination signal from now on
+    signals::defer();
+    // Use the selected language on first play, otherwise the system language if the manifest doesn't set one
+    if !player.began {
+        if let Some(lang) = lang.or_else(|| config.settings.lang.is_none().then(|| resources.system_lang()).flatten()) {
+            player.lang = lang;
+        }
+    }
     // Validate loaded resources
-    resources.validate()?;
+    resources.validate(&config)?;
     // Create input controller
-    let mut input = InputController::new()?;
+    let mut input = InputController::new(&config.settings)?;
     // Begin game loop
-    let silent = begin(&config, &mut player, &saves, &resources, &mut input)
-        .with_context(|| crash_context(&config))?;
+    let silent = begin(&config, &mut player, &saves, &resources, &mut input, &mut save_file)
+        .with_context(|| crash_context(&config, &resources))?;
     // Shut down game with silence based on game loop result
     if !silent {
         println!("Exiting...");
     }
     // Save player data
-    saves.write(&player, save_file, new)?;
+    if config.settings.save {
+        saves.write(&mut player, save_file, matches!(selection, SaveSelection::New))?;
+    }
 
     Ok(())
 }
@@ -49,8 +90,15 @@ fn main() -> Result<()> {
     // Parse CLI command - if 'run', use logic above
     // otherwise, uses its own method
     let command = CliCommand::parse();
-    if let CliCommand::Run { path, pick, new } = command {
-        return run(path.unwrap_or(PathBuf::new()), pick, new);
+    if let CliCommand::Run { path, save, new, choose, profile, no_setup, no_audio, instant, verbose } = command {
+        logging::init(verbose);
+        let selection = match (save, new, choose) {
+            (_, true, _) => SaveSelection::New,
+            (Some(name), ..) => SaveSelection::Named(name),
+            (None, _, true) => SaveSelection::Choose,
+            (None, _, false) => SaveSelection::Continue
+        };
+        return run(path.unwrap_or(PathBuf::new()), selection, profile, no_setup, no_audio, instant);
     }
     command.run()
 }
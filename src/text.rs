@@ -1,3 +1,5 @@
+pub mod conditions;
 pub mod context;
 pub mod display;
+pub mod image;
 pub mod templating;
\ No newline at end of file
@@ -0,0 +1,447 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize, de::Error as DeError};
+use strum::{Display, EnumString};
+
+use crate::core::choice::{VariableList, count_notes};
+
+use super::context::TextContext;
+
+/// The built-in name resolving to the seconds the player has spent playing, such as in `playtime > 300`.
+/// 
+/// A player variable of the same name takes precedence.
+pub const PLAYTIME: &str = "playtime";
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single value within a [`Condition`].
+pub enum Operand {
+	/// A quoted string, number, or boolean written directly in the condition.
+	Literal(String),
+	/// A name resolved against the player's variables and notes or the global `nage:` variables.
+	Variable(String),
+	/// A method called on a collection, either `notes` or a list variable, such as `inventory.contains("sword")`.
+	Call(String, Method, Box<Operand>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+/// A helper method callable on a collection within a [`Condition`].
+pub enum Method {
+	/// Whether the collection contains the argument.
+	Contains,
+	/// The number of elements in the collection equal to the argument.
+	Count
+}
+
+impl Display for Operand {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Operand::Literal(value) => {
+				if value == "true" || value == "false" || value.parse::<f64>().is_ok() {
+					write!(f, "{value}")
+				}
+				else {
+					write!(f, "\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+				}
+			},
+			Operand::Variable(name) => write!(f, "{name}"),
+			Operand::Call(collection, method, argument) => write!(f, "{collection}.{method}({argument})")
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+/// A binary comparison between two [`Operand`]s.
+pub enum Comparison {
+	#[strum(serialize = "==")]
+	Eq,
+	#[strum(serialize = "!=")]
+	Ne,
+	#[strum(serialize = "<")]
+	Lt,
+	#[strum(serialize = "<=")]
+	Le,
+	#[strum(serialize = ">")]
+	Gt,
+	#[strum(serialize = ">=")]
+	Ge
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A parsed boolean expression over player variables and notes.
+///
+/// The grammar, from lowest to highest precedence, is as follows:
+/// - `a or b`
+/// - `a and b`
+/// - `not a`
+/// - `a == b`, `a != b`, `a < b`, `a <= b`, `a > b`, `a >= b`
+/// - `(a)`, a quoted string, a number, `true`/`false`, a name, or a collection method call
+///
+/// Both `and` and `or` short-circuit from left to right.
+///
+/// A lone name is truthy if the player has a note by that name or if a variable by that name is set to
+/// anything other than an empty string, `false`, or `0`. In comparisons, names resolve to their variable value,
+/// or an empty string if unset. Operands are compared numerically when both sides are numbers and
+/// lexicographically otherwise.
+///
+/// The `notes` collection and list variables support the `contains(value)` and `count(value)` methods,
+/// for example `notes.contains("met_guard")` or `inventory.count("potion") >= 2`.
+/// A `notes` argument ending in `:` queries a whole namespace, for example `notes.count("chapter2:") >= 3`.
+pub enum Condition {
+	Or(Box<Condition>, Box<Condition>),
+	And(Box<Condition>, Box<Condition>),
+	Not(Box<Condition>),
+	Compare(Operand, Comparison, Operand),
+	Truthy(Operand)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Name(String),
+	Literal(String),
+	Op(Comparison),
+	And,
+	Or,
+	Not,
+	Open,
+	Close
+}
+
+impl Display for Token {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		use Token::*;
+		match self {
+			Name(name) => write!(f, "'{name}'"),
+			Literal(value) => write!(f, "\"{value}\""),
+			Op(_) => write!(f, "comparison operator"),
+			And => write!(f, "'and'"),
+			Or => write!(f, "'or'"),
+			Not => write!(f, "'not'"),
+			Open => write!(f, "'('"),
+			Close => write!(f, "')'")
+		}
+	}
+}
+
+/// Whether a string value counts as `true` when used on its own.
+pub fn is_truthy(value: &str) -> bool {
+	!(value.is_empty() || value == "false" || value == "0")
+}
+
+impl Condition {
+	/// Whether a character can be part of a name token.
+	fn is_name_char(c: char) -> bool {
+		c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.' | '[' | ']')
+	}
+
+	/// Reads a quoted string literal starting after the opening quote.
+	///
+	/// A backslash escapes the following character.
+	fn read_string(chars: &mut std::iter::Peekable<std::str::Chars>, quote: char) -> Result<String> {
+		let mut result = String::new();
+		loop {
+			match chars.next() {
+				None => return Err(anyhow!("Unterminated string in condition")),
+				Some('\\') => {
+					let escaped = chars.next().ok_or(anyhow!("Unterminated string in condition"))?;
+					result.push(escaped);
+				},
+				Some(c) if c == quote => return Ok(result),
+				Some(c) => result.push(c)
+			}
+		}
+	}
+
+	/// Splits a condition string into tokens.
+	fn tokenize(input: &str) -> Result<Vec<Token>> {
+		use Token::*;
+		let mut tokens = Vec::new();
+		let mut chars = input.chars().peekable();
+		while let Some(&c) = chars.peek() {
+			if c.is_whitespace() {
+				chars.next();
+				continue;
+			}
+			let token = match c {
+				'(' => { chars.next(); Open },
+				')' => { chars.next(); Close },
+				'"' | '\'' => {
+					chars.next();
+					Literal(Self::read_string(&mut chars, c)?)
+				},
+				'=' | '!' | '<' | '>' => {
+					chars.next();
+					let eq = chars.next_if_eq(&'=').is_some();
+					match (c, eq) {
+						('=', true) => Op(Comparison::Eq),
+						('!', true) => Op(Comparison::Ne),
+						('<', false) => Op(Comparison::Lt),
+						('<', true) => Op(Comparison::Le),
+						('>', false) => Op(Comparison::Gt),
+						('>', true) => Op(Comparison::Ge),
+						_ => return Err(anyhow!("Invalid operator '{c}' in condition"))
+					}
+				},
+				_ if Self::is_name_char(c) => {
+					let mut name = String::new();
+					while let Some(c) = chars.next_if(|&c| Self::is_name_char(c)) {
+						name.push(c);
+					}
+					match name.as_str() {
+						"and" => And,
+						"or" => Or,
+						"not" => Not,
+						"true" | "false" => Literal(name),
+						_ if name.parse::<f64>().is_ok() => Literal(name),
+						_ => Name(name)
+					}
+				},
+				_ => return Err(anyhow!("Unexpected character '{c}' in condition"))
+			};
+			tokens.push(token);
+		}
+		Ok(tokens)
+	}
+
+	/// Parses a condition from its string form. See [`Condition`] for the grammar.
+	pub fn parse(input: &str) -> Result<Self> {
+		let tokens = Self::tokenize(input)?;
+		let mut parser = Parser { tokens, pos: 0 };
+		let result = parser.or()?;
+		if let Some(token) = parser.peek() {
+			return Err(anyhow!("Unexpected {token} in condition '{input}'"));
+		}
+		Ok(result)
+	}
+
+	/// Calls a [`Method`] on a collection; either the player's notes or a list variable.
+	fn call(collection: &str, method: Method, argument: &str, context: &TextContext) -> Result<String> {
+		let count = if collection == "notes" {
+			count_notes(&context.notes, argument)
+		}
+		else {
+			let list = VariableList::from_variable(collection, context.variables.get(collection))?;
+			list.0.iter().filter(|value| *value == argument).count()
+		};
+		let result = match method {
+			Method::Contains => (count > 0).to_string(),
+			Method::Count => count.to_string()
+		};
+		Ok(result)
+	}
+
+	/// Resolves a name to a player variable, falling back to a built-in value such as `playtime`.
+	fn variable(name: &str, context: &TextContext) -> Result<Option<String>> {
+		let result = context.variable(name)?.or_else(|| {
+			match name {
+				PLAYTIME => Some(context.playtime.to_string()),
+				_ => None
+			}
+		});
+		Ok(result)
+	}
+
+	/// Resolves an [`Operand`] to its string value.
+	fn resolve(operand: &Operand, context: &TextContext) -> Result<String> {
+		let result = match operand {
+			Operand::Literal(value) => value.clone(),
+			Operand::Variable(name) => Self::variable(name, context)?.unwrap_or_default(),
+			Operand::Call(collection, method, argument) => {
+				Self::call(collection, *method, &Self::resolve(argument, context)?, context)?
+			}
+		};
+		Ok(result)
+	}
+
+	/// Compares two resolved operand values, numerically if possible.
+	fn compare(left: &str, right: &str) -> Option<Ordering> {
+		match (left.parse::<f64>(), right.parse::<f64>()) {
+			(Ok(l), Ok(r)) => l.partial_cmp(&r),
+			_ => Some(left.cmp(right))
+		}
+	}
+
+	/// Evaluates this condition against the player data in a [`TextContext`].
+	pub fn evaluate(&self, context: &TextContext) -> Result<bool> {
+		use Condition::*;
+		let result = match self {
+			Or(left, right) => left.evaluate(context)? || right.evaluate(context)?,
+			And(left, right) => left.evaluate(context)? && right.evaluate(context)?,
+			Not(inner) => !inner.evaluate(context)?,
+			Compare(left, op, right) => {
+				let ordering = Self::compare(&Self::resolve(left, context)?, &Self::resolve(right, context)?);
+				match op {
+					Comparison::Eq => ordering == Some(Ordering::Equal),
+					Comparison::Ne => ordering != Some(Ordering::Equal),
+					Comparison::Lt => ordering == Some(Ordering::Less),
+					Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+					Comparison::Gt => ordering == Some(Ordering::Greater),
+					Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+				}
+			},
+			Truthy(Operand::Variable(name)) => {
+				context.notes.contains(name) || Self::variable(name, context)?.map(|value| is_truthy(&value)).unwrap_or(false)
+			},
+			Truthy(operand) => is_truthy(&Self::resolve(operand, context)?)
+		};
+		Ok(result)
+	}
+
+	/// Splits this condition into the conditions that must all be true for it to be true, flattening nested `and`s.
+	pub fn conjuncts(&self) -> Vec<&Condition> {
+		match self {
+			Condition::And(left, right) => left.conjuncts().into_iter().chain(right.conjuncts()).collect(),
+			_ => vec![self]
+		}
+	}
+
+	/// Collects the names of all collections that methods are called on within this condition.
+	pub fn collections(&self) -> Vec<&str> {
+		fn operand_collections<'a>(operand: &'a Operand, result: &mut Vec<&'a str>) {
+			if let Operand::Call(collection, _, argument) = operand {
+				result.push(collection);
+				operand_collections(argument, result);
+			}
+		}
+		use Condition::*;
+		let mut result = Vec::new();
+		match self {
+			Or(left, right) | And(left, right) => {
+				result.extend(left.collections());
+				result.extend(right.collections());
+			},
+			Not(inner) => result.extend(inner.collections()),
+			Compare(left, _, right) => {
+				operand_collections(left, &mut result);
+				operand_collections(right, &mut result);
+			},
+			Truthy(operand) => operand_collections(operand, &mut result)
+		}
+		result
+	}
+}
+
+impl Display for Condition {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		use Condition::*;
+		match self {
+			Or(left, right) => write!(f, "{left} or {right}"),
+			And(left, right) => {
+				let wrap = |c: &Condition| match c {
+					Or(..) => format!("({c})"),
+					_ => c.to_string()
+				};
+				write!(f, "{} and {}", wrap(left), wrap(right))
+			},
+			Not(inner) => match inner.as_ref() {
+				Or(..) | And(..) => write!(f, "not ({inner})"),
+				_ => write!(f, "not {inner}")
+			},
+			Compare(left, op, right) => write!(f, "{left} {op} {right}"),
+			Truthy(operand) => write!(f, "{operand}")
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Condition {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: serde::Deserializer<'de> {
+		let string = String::deserialize(deserializer)?;
+		Self::parse(&string).map_err(DeError::custom)
+	}
+}
+
+impl Serialize for Condition {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: serde::Serializer {
+		self.to_string().serialize(serializer)
+	}
+}
+
+/// A recursive-descent parser over condition [`Token`]s.
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.pos).cloned();
+		self.pos += 1;
+		token
+	}
+
+	fn or(&mut self) -> Result<Condition> {
+		let mut left = self.and()?;
+		while self.peek() == Some(&Token::Or) {
+			self.next();
+			left = Condition::Or(Box::new(left), Box::new(self.and()?));
+		}
+		Ok(left)
+	}
+
+	fn and(&mut self) -> Result<Condition> {
+		let mut left = self.not()?;
+		while self.peek() == Some(&Token::And) {
+			self.next();
+			left = Condition::And(Box::new(left), Box::new(self.not()?));
+		}
+		Ok(left)
+	}
+
+	fn not(&mut self) -> Result<Condition> {
+		if self.peek() == Some(&Token::Not) {
+			self.next();
+			return Ok(Condition::Not(Box::new(self.not()?)));
+		}
+		self.comparison()
+	}
+
+	fn comparison(&mut self) -> Result<Condition> {
+		if self.peek() == Some(&Token::Open) {
+			self.next();
+			let inner = self.or()?;
+			return match self.next() {
+				Some(Token::Close) => Ok(inner),
+				_ => Err(anyhow!("Expected ')' in condition"))
+			};
+		}
+		let left = self.operand()?;
+		if let Some(&Token::Op(op)) = self.peek() {
+			self.next();
+			return Ok(Condition::Compare(left, op, self.operand()?));
+		}
+		Ok(Condition::Truthy(left))
+	}
+
+	/// Parses a method call's parenthesized argument if the name is of the form `collection.method`.
+	fn call(&mut self, name: &str) -> Result<Option<Operand>> {
+		let Some((collection, method)) = name.rsplit_once('.') else {
+			return Ok(None);
+		};
+		let Ok(method) = method.parse::<Method>() else {
+			return Ok(None);
+		};
+		if self.peek() != Some(&Token::Open) {
+			return Err(anyhow!("Expected '(' after method '{name}'"));
+		}
+		self.next();
+		let argument = self.operand()?;
+		if self.next() != Some(Token::Close) {
+			return Err(anyhow!("Expected ')' after argument to '{name}'"));
+		}
+		Ok(Some(Operand::Call(collection.to_owned(), method, Box::new(argument))))
+	}
+
+	fn operand(&mut self) -> Result<Operand> {
+		match self.next() {
+			Some(Token::Name(name)) => Ok(self.call(&name)?.unwrap_or(Operand::Variable(name))),
+			Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+			Some(token) => Err(anyhow!("Expected a value in condition, found {token}")),
+			None => Err(anyhow!("Unexpected end of condition"))
+		}
+	}
+}
@@ -1,8 +1,12 @@
+use std::{cell::RefCell, collections::{HashMap, BTreeSet}};
+
+use anyhow::{Result, anyhow};
+use result::OptionResultExt;
 use rlua::{Context, Table};
 
-use crate::core::{manifest::Manifest, choice::{Notes, Variables}, scripts::Scripts, audio::Audio, resources::Resources};
+use crate::core::{prompt::Prompts, manifest::Manifest, choice::{Notes, Variables, VariableList}, scripts::Scripts, audio::Audio, resources::{Resources, UnlockedInfoPages}};
 
-use super::display::TranslationFile;
+use super::{display::{TranslationFile, PlayerTextSpeed}, image::Images};
 
 /// A wrapper for all data relevant for filling in [`TemplatableString`]s.
 /// 
@@ -12,45 +16,135 @@ pub struct TextContext<'a> {
 	pub config: &'a Manifest,
 	pub notes: Notes,
 	pub variables: Variables,
+	/// The sound channels enabled by the player.
+	pub channels: BTreeSet<String>,
+	/// The total seconds the player has spent playing.
+	pub playtime: u64,
 	pub lang: String,
+	/// The player's preferred text speed.
+	pub speed: PlayerTextSpeed,
+	/// The info pages the player has unlocked.
+	pub info_pages: UnlockedInfoPages,
 	pub lang_file: Option<&'a TranslationFile>,
 	pub scripts: &'a Scripts,
-	pub audio: &'a Option<Audio>
+	pub prompts: &'a Prompts,
+	/// The file of the prompt the player is on, which relative `jump` paths are resolved against.
+	pub file: Option<String>,
+	pub audio: &'a Option<Audio>,
+	pub images: &'a Images,
+	/// Global variables that have already been resolved using this context.
+	/// 
+	/// A context is short-lived, so this also keeps values such as the time consistent within a single render.
+	globals: RefCell<HashMap<String, Option<String>>>
 }
 
 impl<'a> TextContext<'a> {
 	/// Constructs a new [`TextContext`] by accessing [`Resources`] internals.
-	pub fn new(config: &'a Manifest, notes: Notes, variables: Variables, lang: &str, resources: &'a Resources) -> Self {
+	pub fn new(config: &'a Manifest, notes: Notes, variables: Variables, channels: BTreeSet<String>, playtime: u64, lang: &str, resources: &'a Resources) -> Self {
 		TextContext { 
 			config, 
 			notes,
 			variables,
+			channels,
+			playtime,
 			lang: lang.to_owned(),
+			speed: PlayerTextSpeed::default(),
+			info_pages: UnlockedInfoPages::new(),
 			lang_file: resources.lang_file(lang), 
 			scripts: &resources.scripts,
-			audio: &resources.audio
+			prompts: &resources.prompts,
+			file: None,
+			audio: &resources.audio,
+			images: &resources.images,
+			globals: RefCell::new(HashMap::new())
 		}
 	}
 
+	/// Resolves a `nage:`-prefixed global variable, memoizing the result.
 	pub fn global_variable(&self, var: &str) -> Option<String> {
+		if let Some(value) = self.globals.borrow().get(var) {
+			return value.clone();
+		}
+		let value = self.resolve_global_variable(var);
+		self.globals.borrow_mut().insert(var.to_owned(), value.clone());
+		value
+	}
+
+	/// Formats an amount of seconds played as a short duration such as `45s`, `13m`, or `2h 13m`.
+	fn format_playtime(seconds: u64) -> String {
+		let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+		match (hours, minutes) {
+			(0, 0) => format!("{seconds}s"),
+			(0, _) => format!("{minutes}m"),
+			_ => format!("{hours}h {minutes}m")
+		}
+	}
+
+	fn resolve_global_variable(&self, var: &str) -> Option<String> {
 		var.to_lowercase().strip_prefix("nage:").map(|name| {
 			match name {
 				"game_name" => Some(self.config.metadata.name.clone()),
 				"game_authors" => Some(self.config.metadata.authors.join(", ")),
 				"game_version" => Some(self.config.metadata.version.to_string()),
 				"lang" => Some(self.lang.to_owned()),
+				"playtime" => Some(Self::format_playtime(self.playtime)),
+				"playtime_seconds" => Some(self.playtime.to_string()),
 				_ => None
 			}
 		})
 		.flatten()
 	}
 
+	/// Resolves a list access such as `inventory[0]` or `inventory.length`, if the name is one.
+	fn list_access(&self, name: &str) -> Option<Result<String>> {
+		if let Some(list) = name.strip_suffix(".length") {
+			let result = VariableList::from_variable(list, self.variables.get(list))
+				.map(|values| values.0.len().to_string());
+			return Some(result);
+		}
+		let (list, index) = name.strip_suffix(']')?.split_once('[')?;
+		let result = index.trim().parse::<usize>()
+			.map_err(|_| anyhow!("Invalid index '{index}' for list '{list}'"))
+			.and_then(|index| {
+				let values = VariableList::from_variable(list, self.variables.get(list))?;
+				values.get(list, index).cloned()
+			});
+		Some(result)
+	}
+
+	/// Resolves a variable name to its value, preferring global variables over player variables.
+	/// 
+	/// List variables resolve to their elements joined by commas. Their elements can be accessed
+	/// with `name[index]` and their length with `name.length`; an out-of-bounds index is an error.
+	pub fn variable(&self, name: &str) -> Result<Option<String>> {
+		if let Some(global) = self.global_variable(name) {
+			return Ok(Some(global));
+		}
+		if let Some(value) = self.variables.get(name) {
+			let result = VariableList::parse(value)
+				.map(|list| list.to_string())
+				.unwrap_or(value.clone());
+			return Ok(Some(result));
+		}
+		self.list_access(name).invert()
+	}
+
+	/// Creates the `nage` Lua table of game data and functions such as `is_playing(channel)` and `current_track(channel)`.
 	pub fn create_variable_table<'b>(&self, context: &Context<'b>) -> Result<Table<'b>, rlua::Error> {
 		let table = context.create_table()?;
 		table.set("game_name", self.config.metadata.name.clone())?;
 		table.set("game_authors", context.create_sequence_from(self.config.metadata.authors.clone())?)?;
 		table.set("game_version", self.config.metadata.version.to_string())?;
 		table.set("lang", self.lang.to_owned())?;
+		// Scripts can't borrow the audio system, so the functions read a snapshot of it
+		let channels = self.audio.as_ref().map(Audio::channel_states).unwrap_or_default();
+		let tracks = channels.clone();
+		table.set("is_playing", context.create_function(move |_, channel: String| {
+			Ok(channels.get(&channel).map(|state| state.playing).unwrap_or(false))
+		})?)?;
+		table.set("current_track", context.create_function(move |_, channel: String| {
+			Ok(tracks.get(&channel).and_then(|state| state.track.clone()))
+		})?)?;
 		Ok(table)
 	}
 }
\ No newline at end of file
@@ -1,14 +1,18 @@
-use std::{fmt::{Display, Debug}, time::Duration};
+use std::{fmt::{Display, Debug}, collections::BTreeSet, io::{IsTerminal, Write}, time::Instant};
 
-use anyhow::Result;
-use crossterm::style::Stylize;
-use serde::{Deserialize, Serialize};
+use anyhow::{Result, anyhow};
+use result::OptionResultExt;
+use crossterm::{execute, style::{Stylize, Print}, terminal::{Clear, ClearType}};
+use serde::{Deserialize, Serialize, Deserializer, Serializer, de::{IntoDeserializer, Error as DeError}};
 use snailshell::{snailprint_s, snailprint_d};
-use strum::{EnumString, Display};
+use strum::{EnumString, Display, EnumIter};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_truncate::{UnicodeTruncateStr, Alignment};
+use unicode_width::UnicodeWidthStr;
 
-use crate::loading::base::{ContentFile, Contents};
+use crate::{core::{choice::SoundAction, manifest::Settings}, game::{input::InputController, signals}, loading::base::{ContentFile, Contents}};
 
-use super::{templating::{TemplatableValue, TemplatableString}, context::TextContext};
+use super::{templating::{TemplatableValue, TemplatableString, TemplatableBool}, context::TextContext, image::TextImage};
 
 #[derive(Deserialize, Serialize, Display, Debug, PartialEq, Clone, EnumString)]
 #[serde(rename_all = "snake_case")]
@@ -21,7 +25,9 @@ pub enum TextMode {
 	/// Returns text as-is.
 	Action,
 	/// Prefixes text with a quote character.
-	System
+	System,
+	/// Frames text in a monospace block bordered with box-drawing characters.
+	Code
 }
 
 impl Default for TextMode {
@@ -39,7 +45,166 @@ impl TextMode {
 		match self {
 			Dialogue => format!("\"{text}\""),
 			Action => text.to_owned(),
-			System => format!("{} {text}", "▐".dark_grey())
+			System => format!("{} {text}", Self::SYSTEM_MARKER.dark_grey()),
+			Code => Self::frame(text, Alignment::Left)
+		}
+	}
+
+	/// The marker preceding [`System`](TextMode::System) text.
+	const SYSTEM_MARKER: &'static str = "▐";
+
+	/// Formats text for a right-to-left language.
+	/// 
+	/// Terminals generally display characters left to right, so each line's grapheme clusters are reversed
+	/// and quotes and markers end up on the opposite side. The result is aligned by [`Text::get_directed`].
+	/// Characters aren't shaped, and left-to-right runs such as numbers are reversed as well.
+	pub fn format_rtl(&self, text: &str) -> String {
+		use TextMode::*;
+		match self {
+			Dialogue => Self::reverse_lines(&format!("\"{text}\"")),
+			Action => Self::reverse_lines(text),
+			System => {
+				// Style the marker after reversing so that its escape codes stay intact
+				let reversed = Self::reverse_lines(&format!("{} {text}", Self::SYSTEM_MARKER));
+				let (first, rest) = reversed.split_once('\n').unwrap_or((&reversed, ""));
+				let first = first.strip_suffix(Self::SYSTEM_MARKER).unwrap_or(first);
+				let rest = if rest.is_empty() { String::new() } else { format!("\n{rest}") };
+				format!("{first}{}{rest}", Self::SYSTEM_MARKER.dark_grey())
+			},
+			Code => Self::frame(&Self::reverse_lines(text), Alignment::Right)
+		}
+	}
+
+	/// The amount of columns in the terminal, if known.
+	fn terminal_width() -> Option<usize> {
+		crossterm::terminal::size()
+			.map(|(columns, _)| columns as usize)
+			.ok()
+	}
+
+	/// Reverses the order of the grapheme clusters within each line of some text.
+	fn reverse_lines(text: &str) -> String {
+		text.lines()
+			.map(|line| line.graphemes(true).rev().collect::<String>())
+			.collect::<Vec<String>>()
+			.join("\n")
+	}
+
+	/// Draws a box around each line of some text, wrapping lines that don't fit in the terminal.
+	/// Lines are aligned within the box according to `alignment`.
+	fn frame(text: &str, alignment: Alignment) -> String {
+		// Two columns of border and two of padding
+		let max_width = Self::terminal_width()
+			.map(|columns| columns.saturating_sub(4).max(1))
+			.unwrap_or(usize::MAX);
+		let mut lines = Vec::new();
+		for line in text.lines() {
+			let mut rest = line;
+			loop {
+				let (head, width) = rest.unicode_truncate(max_width);
+				// A character wider than the frame can't be split any further
+				let head = if head.is_empty() { rest } else { head };
+				lines.push((head, width));
+				rest = &rest[head.len()..];
+				if rest.is_empty() {
+					break;
+				}
+			}
+		}
+		let width = lines.iter().map(|(_, width)| *width).max().unwrap_or(0);
+		let border = "─".repeat(width + 2);
+		let body = lines.into_iter()
+			.map(|(line, _)| format!("│ {} │", line.unicode_pad(width, alignment, false)))
+			.collect::<Vec<String>>()
+			.join("\n");
+		format!("┌{border}┐\n{body}\n└{border}┘")
+	}
+}
+
+#[derive(Deserialize, Serialize, Display, Debug, Clone, Copy, PartialEq, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+/// The horizontal alignment of formatted text within the terminal.
+pub enum TextAlign {
+	Left,
+	Center,
+	Right
+}
+
+impl TextAlign {
+	/// Pads each line of some formatted text on the left so that it's aligned within the terminal's width.
+	/// 
+	/// Alignment applies after formatting, so a [`Code`](TextMode::Code) frame, which already wraps to the terminal's width,
+	/// is positioned as a whole. Escape codes don't count towards a line's width, and lines that don't fit are left as-is.
+	/// Text is left-aligned if the output isn't a terminal.
+	pub fn apply(&self, text: &str) -> String {
+		use TextAlign::*;
+		let width = match self {
+			Left => None,
+			_ if !std::io::stdout().is_terminal() => None,
+			_ => TextMode::terminal_width()
+		};
+		let Some(width) = width else {
+			return text.to_owned();
+		};
+		text.lines()
+			.map(|line| {
+				let padding = width.saturating_sub(Self::visible_width(line));
+				let left = match self {
+					Left => 0,
+					Center => padding / 2,
+					Right => padding
+				};
+				format!("{}{line}", " ".repeat(left))
+			})
+			.collect::<Vec<String>>()
+			.join("\n")
+	}
+
+	/// The displayed width of a line, skipping any escape codes.
+	fn visible_width(line: &str) -> usize {
+		let mut visible = String::new();
+		let mut chars = line.chars();
+		while let Some(c) = chars.next() {
+			if c == '\x1b' {
+				// Skip to the final letter of the escape sequence
+				for c in chars.by_ref() {
+					if c.is_ascii_alphabetic() {
+						break;
+					}
+				}
+				continue;
+			}
+			visible.push(c);
+		}
+		visible.width()
+	}
+}
+
+#[derive(Deserialize, Serialize, Display, Debug, Clone, Copy, PartialEq, Default, EnumIter)]
+#[serde(rename_all = "snake_case")]
+/// A player's preferred text speed relative to the speeds set by the game.
+pub enum PlayerTextSpeed {
+	/// Uses the game's text speeds as-is.
+	#[default]
+	Game,
+	/// Prints text at half the game's speed.
+	Slow,
+	/// Prints text at three times the game's speed.
+	Fast,
+	/// Prints text immediately.
+	Instant
+}
+
+impl PlayerTextSpeed {
+	/// The factor by which the game's text speeds are multiplied.
+	pub fn factor(&self) -> f32 {
+		use PlayerTextSpeed::*;
+		match self {
+			Game => 1.0,
+			Slow => 0.5,
+			Fast => 3.0,
+			Instant => f32::INFINITY
 		}
 	}
 }
@@ -85,15 +250,121 @@ impl TextSpeed {
 	/// 
 	/// Otherwise, if the object is [`Duration`](TextSpeed::Duration), uses [`snailprint_d`] with the
 	/// specified length of time.
+	/// 
+	/// Either way, the speed is scaled by the player's [`PlayerTextSpeed`].
 	pub fn print<T>(&self, content: &T, context: &TextContext) -> Result<()> where T: Display {
+		let factor = context.speed.factor();
 		let result = match &self {
-			TextSpeed::Duration(duration) => snailprint_d(content, duration.get_value(context)? as f32 / 1000.0),
-			_ => snailprint_s(content, self.rate(context)?)
+			TextSpeed::Duration(duration) => snailprint_d(content, duration.get_value(context)? as f32 / 1000.0 / factor),
+			_ => snailprint_s(content, self.rate(context)? * factor)
 		};
 		Ok(result)
 	}
 }
 
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+/// A [`TextSpeed`] given inline or by the name of a preset defined in the `speed_presets` setting.
+pub enum TextSpeedRef {
+	Preset(String),
+	Inline(TextSpeed)
+}
+
+impl TextSpeedRef {
+	/// Returns the inline speed or looks up the preset in the settings.
+	pub fn get<'a>(&'a self, settings: &'a Settings) -> Result<&'a TextSpeed> {
+		use TextSpeedRef::*;
+		match self {
+			Preset(name) => settings.speed_presets.get(name)
+				.ok_or(anyhow!("Invalid speed preset '{name}'")),
+			Inline(speed) => Ok(speed)
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for TextSpeedRef {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		match serde_yaml::Value::deserialize(deserializer)? {
+			serde_yaml::Value::String(name) => Ok(TextSpeedRef::Preset(name)),
+			// Reparse the inline speed from its source so that its templatable values can read scalars as strings
+			value => serde_yaml::to_string(&value)
+				.and_then(|source| serde_yaml::from_str(&source))
+				.map(TextSpeedRef::Inline)
+				.map_err(DeError::custom)
+		}
+	}
+}
+
+#[derive(Debug)]
+/// How long to pause after a line of text has been printed.
+pub enum TextWait {
+	/// Waits until the player presses any key.
+	Key,
+	/// Waits for an amount of milliseconds.
+	Duration(TemplatableValue<u64>)
+}
+
+impl TextWait {
+	/// The value of the `wait` key that selects [`TextWait::Key`].
+	pub const KEY: &'static str = "key";
+
+	/// Pauses based on this wait mode.
+	/// 
+	/// For [`TextWait::Key`], prints the configured `wait_hint` while waiting.
+	/// For [`TextWait::Duration`], animates the configured `wait_indicator` if the wait is long enough.
+	pub fn wait(&self, context: &TextContext) -> Result<()> {
+		use TextWait::*;
+		match self {
+			Key => InputController::wait_for_key(&context.config.settings.wait_hint.fill(context)?),
+			Duration(millis) => {
+				let duration = std::time::Duration::from_millis(millis.get_value(context)?);
+				let indicator = &context.config.settings.wait_indicator;
+				if indicator.style.frames().is_empty() || duration.as_millis() < indicator.threshold as u128 || !std::io::stdout().is_terminal() {
+					std::thread::sleep(duration);
+					return Ok(());
+				}
+				Self::animate(indicator.style.frames(), duration, std::time::Duration::from_millis(indicator.interval.max(1)))
+			}
+		}
+	}
+
+	/// Displays animation frames in a loop for a duration, clearing the line afterward.
+	fn animate(frames: &[&str], duration: std::time::Duration, interval: std::time::Duration) -> Result<()> {
+		let mut stdout = std::io::stdout();
+		let end = Instant::now() + duration;
+		for frame in frames.iter().cycle() {
+			let now = Instant::now();
+			if now >= end {
+				break;
+			}
+			execute!(stdout, Clear(ClearType::CurrentLine), Print(format!("\r{frame}")))?;
+			std::thread::sleep(interval.min(end - now));
+		}
+		execute!(stdout, Clear(ClearType::CurrentLine), Print("\r"))?;
+		Ok(())
+	}
+}
+
+impl<'de> Deserialize<'de> for TextWait {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		let string = String::deserialize(deserializer)?;
+		if string == Self::KEY {
+			return Ok(TextWait::Key);
+		}
+		let millis = TemplatableValue::deserialize(string.into_deserializer())?;
+		Ok(TextWait::Duration(millis))
+	}
+}
+
+impl Serialize for TextWait {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+		match self {
+			TextWait::Key => serializer.serialize_str(Self::KEY),
+			TextWait::Duration(millis) => millis.serialize(serializer)
+		}
+	}
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 /// A formattable piece of text.
@@ -104,37 +375,152 @@ pub struct Text {
 	#[serde(default)]
 	/// The mode in which the text content should be formatted upon retrieval.
 	pub mode: TemplatableValue<TextMode>,
-	pub speed: Option<TextSpeed>,
-	pub newline: Option<TemplatableValue<bool>>,
-	pub wait: Option<TemplatableValue<u64>>
+	pub speed: Option<TextSpeedRef>,
+	pub newline: Option<TemplatableBool>,
+	/// The amount of blank lines printed before this line, overriding `newline`. A value of `0` prints none.
+	pub blank_lines: Option<TemplatableValue<usize>>,
+	pub wait: Option<TextWait>,
+	/// A sound action applied as this line starts printing.
+	pub sound: Option<SoundAction>,
+	/// An image displayed before the text content.
+	pub image: Option<TextImage>,
+	/// The amount of times the content is printed.
+	pub repeat: Option<TemplatableValue<usize>>,
+	/// The horizontal alignment of the formatted content. See [`Text::get_directed`] for the default.
+	pub align: Option<TemplatableValue<TextAlign>>
 }
 
 /// An ordered list of text objects.
 pub type TextLines = Vec<Text>;
-/// An ordered list of text objects with a flag representing whether the last entry was of the same [`TextMode`].
-pub type SeparatedTextLines<'a> = Vec<(bool, &'a Text)>;
+/// An ordered list of text objects paired with the amount of blank lines printed before each. See [`Text::blank_lines`].
+pub type SeparatedTextLines<'a> = Vec<(usize, &'a Text)>;
 
 pub type TranslationFile = ContentFile<String>;
 pub type Translations = Contents<String>;
 
+/// How well a single [`TranslationFile`] covers the translation keys used by the game's content.
+pub struct TranslationCoverage<'a> {
+	/// Keys used in content that the file doesn't define.
+	pub missing: Vec<&'a str>,
+	/// Keys the file defines that no content uses.
+	pub extra: Vec<&'a str>
+}
+
+impl<'a> TranslationCoverage<'a> {
+	/// Compares the used keys against a translation file.
+	pub fn new(used: &BTreeSet<&'a str>, file: &'a TranslationFile) -> Self {
+		TranslationCoverage {
+			missing: used.iter()
+				.filter(|key| !file.contains_key(**key))
+				.copied()
+				.collect(),
+			extra: file.keys()
+				.map(|key| key.as_str())
+				.filter(|key| !used.contains(key))
+				.collect()
+		}
+	}
+
+	pub fn is_complete(&self) -> bool {
+		self.missing.is_empty() && self.extra.is_empty()
+	}
+}
+
 impl Text {
+	/// Creates an [`Action`](TextMode::Action) text object displaying the content literally.
+	pub fn literal(content: &str) -> Self {
+		Text {
+			content: TemplatableString::literal(content),
+			mode: TemplatableValue::value(TextMode::Action),
+			speed: None,
+			newline: None,
+			blank_lines: None,
+			wait: None,
+			sound: None,
+			image: None,
+			repeat: None,
+			align: None
+		}
+	}
+
 	/// Retrieves text content with [`TemplatableString::fill`] and formats it based on the [`TextMode`].
 	pub fn get(&self, context: &TextContext) -> Result<String> {
 		Ok(self.mode.get_value(context)?.format(&self.content.fill(context)?))
 	}
 
+	/// Calls [`Text::get`], instead formatting the content with [`TextMode::format_rtl`] if the current language is right-to-left,
+	/// and aligns the result according to the `align` key.
+	/// 
+	/// If a `width` is given, lines are word-wrapped to it before aligning so that the terminal doesn't split words.
+	/// Without an `align` key, right-to-left text is aligned right and other text is left as-is.
+	fn get_directed(&self, context: &TextContext, width: Option<usize>) -> Result<String> {
+		let rtl = context.config.settings.rtl_languages.contains(&context.lang);
+		let text = if rtl {
+			self.mode.get_value(context)?.format_rtl(&self.content.fill(context)?)
+		}
+		else {
+			self.get(context)?
+		};
+		let text = match width {
+			Some(width) => textwrap::fill(&text, width),
+			None => text
+		};
+		let default = if rtl { TextAlign::Right } else { TextAlign::Left };
+		let align = self.align.as_ref().map(|align| align.get_value(context)).invert()?.unwrap_or(default);
+		Ok(align.apply(&text))
+	}
+
 	/// Formats and snailprints text based on its [`TextSpeed`]. 
 	/// 
 	/// If the text object does not contain a `speed` field, defaults to the provided config settings.
+	/// Named speeds are resolved against the `speed_presets` setting.
+	/// Any `image` is displayed before printing. If the output is a terminal, the content is wrapped to its current width.
+	/// 
+	/// The content is printed `repeat` times, defaulting to once. Each time, any `sound` action is applied beforehand
+	/// and any `wait` is performed afterward. A `repeat` of `0` prints nothing at all.
+	/// 
+	/// If the `instant_text` setting is enabled, the content is printed at once and waits are skipped.
 	pub fn print(&self, context: &TextContext) -> Result<()> {
-		let speed = self.speed.as_ref().unwrap_or(&context.config.settings.speed);
-		speed.print(&self.get(context)?, context)?;
-		if let Some(wait) = &self.wait {
-			std::thread::sleep(Duration::from_millis(wait.get_value(context)?));
+		let repeat = self.repeat(context)?;
+		if repeat == 0 {
+			return Ok(());
+		}
+		if let Some(image) = &self.image {
+			image.print(context)?;
+		}
+		let settings = &context.config.settings;
+		let speed = self.speed.as_ref()
+			.map(|speed| speed.get(settings))
+			.unwrap_or(Ok(&settings.speed))?;
+		// Measured for every line so that a terminal resized between lines wraps at its new width
+		let width = std::io::stdout().is_terminal().then(TextMode::terminal_width).flatten();
+		let content = self.get_directed(context, width)?;
+		for _ in 0..repeat {
+			if let (Some(sound), Some(audio)) = (&self.sound, context.audio) {
+				audio.accept(&context.channels, sound, context)?;
+			}
+			if settings.instant_text {
+				println!("{content}");
+				continue;
+			}
+			speed.print(&content, context)?;
+			if signals::shutdown_requested() {
+				break;
+			}
+			if let Some(wait) = &self.wait {
+				wait.wait(context)?;
+			}
 		}
 		Ok(())
 	}
 
+	/// The amount of times the content is printed according to the `repeat` key, defaulting to once.
+	fn repeat(&self, context: &TextContext) -> Result<usize> {
+		self.repeat.as_ref()
+			.map(|repeat| repeat.get_value(context))
+			.unwrap_or(Ok(1))
+	}
+
 	/// Whether a newline should be printed before this line.
 	/// Uses the `newline` key, otherwise defaulting to comparing the [`TextMode`] between this and the previous line, if any.
 	fn newline(&self, previous: Option<&Text>, context: &TextContext) -> Result<bool> {
@@ -146,28 +532,76 @@ impl Text {
 			)
 	}
 
+	/// The amount of blank lines printed before this line.
+	/// Uses the `blank_lines` key, otherwise printing one if [`Text::newline`] passes.
+	fn blank_lines(&self, previous: Option<&Text>, context: &TextContext) -> Result<usize> {
+		match &self.blank_lines {
+			Some(amount) => amount.get_value(context),
+			None => Ok(self.newline(previous, context)? as usize)
+		}
+	}
+
 	/// Calculates some [`SeparatedTextLines`] based on some text lines.
 	fn get_separated_lines<'a>(lines: &'a TextLines, context: &TextContext) -> Result<SeparatedTextLines<'a>> {
 		lines.iter().enumerate()
-    		.map(|(index, line)| Ok((line.newline(index.checked_sub(1).map(|i| &lines[i]), context)?, line)))
+    		.map(|(index, line)| Ok((line.blank_lines(index.checked_sub(1).map(|i| &lines[i]), context)?, line)))
     		.collect()
 	}
 
 	/// Formats and separates text lines and prints them sequentially.
 	pub fn print_lines(lines: &TextLines, context: &TextContext) -> Result<()> {
-		for (newline, line) in Self::get_separated_lines(lines, context)? {
-			if newline {
-				println!();
+		for (blank_lines, line) in Self::get_separated_lines(lines, context)? {
+			// Leave the rest of the text unprinted so that the game loop can shut down
+			if signals::shutdown_requested() {
+				break;
 			}
+			print!("{}", "\n".repeat(blank_lines));
 			line.print(context)?;
 		}
 		Ok(())
 	}
 
+	/// Writes formatted and separated text lines to an output at once, for deterministic transcripts.
+	/// 
+	/// Lines are repeated according to their `repeat` key, but no sounds, images, or waits are applied.
+	pub fn write_lines(lines: &TextLines, context: &TextContext, out: &mut dyn Write) -> Result<()> {
+		for (blank_lines, line) in Self::get_separated_lines(lines, context)? {
+			write!(out, "{}", "\n".repeat(blank_lines))?;
+			let content = line.get_directed(context, None)?;
+			for _ in 0..line.repeat(context)? {
+				writeln!(out, "{content}")?;
+			}
+		}
+		Ok(())
+	}
+
 	/// Calls [`Text::print_lines`] and prints a newline at the end.
 	pub fn print_lines_nl(lines: &TextLines, context: &TextContext) -> Result<()> {
 		Self::print_lines(lines, context)?;
 		println!();
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use unicode_segmentation::UnicodeSegmentation;
+
+	use super::TextMode;
+
+	/// A family emoji joined by zero-width joiners, followed by an `e` with a combining acute accent.
+	const CLUSTERS: [&str; 2] = ["👩\u{200D}👩\u{200D}👧", "e\u{301}"];
+
+	#[test]
+	fn formatted_line_keeps_clusters() {
+		// Snailprinting advances by the grapheme clusters of the formatted line
+		let formatted = TextMode::Dialogue.format(&CLUSTERS.concat());
+		assert_eq!(formatted.graphemes(true).collect::<Vec<&str>>(), ["\"", CLUSTERS[0], CLUSTERS[1], "\""]);
+	}
+
+	#[test]
+	fn reversed_line_keeps_clusters() {
+		let reversed = TextMode::Action.format_rtl(&CLUSTERS.concat());
+		assert_eq!(reversed.graphemes(true).collect::<Vec<&str>>(), [CLUSTERS[1], CLUSTERS[0]]);
+	}
+}
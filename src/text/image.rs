@@ -0,0 +1,125 @@
+use std::io::{Write, IsTerminal};
+
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::loading::{base::Loader, assets::{AssetFiles, AssetKind}};
+
+use super::{templating::TemplatableString, context::TextContext};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A terminal graphics protocol able to display images inline.
+pub enum GraphicsProtocol {
+	/// The Kitty graphics protocol, which displays PNG images.
+	Kitty,
+	/// The iTerm2 inline images protocol, which displays any image format the terminal supports.
+	Iterm
+}
+
+impl GraphicsProtocol {
+	/// The largest amount of encoded data sent in a single Kitty graphics command.
+	const KITTY_CHUNK_SIZE: usize = 4096;
+
+	/// Detects the graphics protocol supported by the current terminal from its environment variables.
+	///
+	/// Returns [`None`] if the output isn't a terminal or no supported protocol is detected.
+	pub fn detect() -> Option<Self> {
+		if !std::io::stdout().is_terminal() {
+			return None;
+		}
+		let var = |name: &str| std::env::var(name).unwrap_or_default();
+		if std::env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM") == "xterm-kitty" {
+			return Some(GraphicsProtocol::Kitty);
+		}
+		match var("TERM_PROGRAM").as_str() {
+			"iTerm.app" | "WezTerm" => Some(GraphicsProtocol::Iterm),
+			_ => None
+		}
+	}
+
+	/// Whether this protocol can display the image data.
+	fn supports(&self, data: &[u8]) -> bool {
+		match self {
+			GraphicsProtocol::Kitty => data.starts_with(b"\x89PNG"),
+			GraphicsProtocol::Iterm => true
+		}
+	}
+
+	/// Encodes image data into the escape sequences that display it.
+	fn encode(&self, data: &[u8]) -> String {
+		let encoded = STANDARD.encode(data);
+		match self {
+			GraphicsProtocol::Kitty => {
+				// Kitty requires data to be sent in chunks, each noting whether more follow
+				let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(Self::KITTY_CHUNK_SIZE).collect();
+				chunks.iter().enumerate()
+					.map(|(index, chunk)| {
+						let keys = if index == 0 { "a=T,f=100," } else { "" };
+						let more = if index + 1 < chunks.len() { 1 } else { 0 };
+						format!("\x1b_G{keys}m={more};{}\x1b\\", String::from_utf8_lossy(chunk))
+					})
+					.collect()
+			},
+			GraphicsProtocol::Iterm => format!("\x1b]1337;File=inline=1;size={}:{encoded}\x07", data.len())
+		}
+	}
+}
+
+/// The image files of a game along with the terminal's graphics support.
+pub struct Images {
+	pub files: AssetFiles,
+	pub protocol: Option<GraphicsProtocol>
+}
+
+impl Images {
+	/// Locates the image files using [`AssetFiles`] and detects the terminal's [`GraphicsProtocol`].
+	///
+	/// Image data is only read when an image is displayed.
+	pub fn load(loader: &Loader) -> Result<Self> {
+		let result = Images {
+			files: AssetFiles::load(loader, AssetKind::Image)?,
+			protocol: GraphicsProtocol::detect()
+		};
+		Ok(result)
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+/// An image displayed inline before a line of text.
+pub struct TextImage {
+	/// The name of the image file within the `assets/images` or `images` directory.
+	pub name: String,
+	/// The text printed in place of the image when it can't be displayed.
+	pub alt: Option<TemplatableString>
+}
+
+impl TextImage {
+	/// Displays the image using the detected [`GraphicsProtocol`].
+	///
+	/// If the terminal has no graphics support, the image file is missing, or the terminal can't display its format,
+	/// prints the `alt` text, falling back to the image name.
+	pub fn print(&self, context: &TextContext) -> Result<()> {
+		if let Some(protocol) = context.images.protocol {
+			match context.images.files.get(&self.name) {
+				Ok(path) => {
+					let data = std::fs::read(path)?;
+					if protocol.supports(&data) {
+						let mut stdout = std::io::stdout();
+						writeln!(stdout, "{}", protocol.encode(&data))?;
+						stdout.flush()?;
+						return Ok(());
+					}
+				},
+				Err(err) => log::warn!("{err}; displaying its alternative text instead")
+			}
+		}
+		let alt = match &self.alt {
+			Some(alt) => alt.fill(context)?,
+			None => self.name.clone()
+		};
+		println!("[{alt}]");
+		Ok(())
+	}
+}
@@ -3,9 +3,9 @@ use std::str::FromStr;
 use anyhow::{Result, anyhow, Context};
 use serde::{Deserialize, Serialize, de::{DeserializeOwned, Error as DeError}, Deserializer};
 
-use crate::core::choice::Variables;
+use crate::core::scripts::Scripts;
 
-use super::{display::TranslationFile, context::TextContext};
+use super::{display::TranslationFile, context::TextContext, conditions::Condition};
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(transparent)]
@@ -24,11 +24,34 @@ impl From<String> for TemplatableString {
 impl TemplatableString {
 	/// The default value for an undefined interpolation component.
 	pub const DEFAULT_VALUE: &'static str = "UNDEFINED";
+	/// The value substituted for a failing script component in debug mode.
+	pub const SCRIPT_ERROR_VALUE: &'static str = "SCRIPT ERROR";
 
-	/// Whether this string's content can be **templated** by variables or scripts.
+	/// The opening delimiter of an inline expression such as `{{ if cond then a else b }}`.
+	pub const EXPRESSION_OPEN: &'static str = "{{";
+	/// The closing delimiter of an inline expression.
+	pub const EXPRESSION_CLOSE: &'static str = "}}";
+
+	/// The character that makes the following delimiter character literal, such as in `\{{` or `\<`.
+	pub const ESCAPE: char = '\\';
+	/// The characters that can be escaped, including the escape character itself.
+	const ESCAPABLE: [char; 7] = ['{', '}', '(', ')', '<', '>', Self::ESCAPE];
+	/// The first of the private-use characters that stand in for escaped characters while templating.
+	const ESCAPED_BASE: u32 = 0xE000;
+
+	/// Whether this string's content can be **templated** by variables, scripts, or expressions.
 	/// This does not check for language file matching.
 	pub fn is_str_templatable(content: &str) -> bool {
-		content.contains('(') || content.contains('<')
+		content.contains('(') || content.contains('<') || content.contains(Self::EXPRESSION_OPEN)
+	}
+
+	/// Whether this string's content looks like a translation key such as `some.key.here` rather than literal text.
+	/// 
+	/// A key consists of at least two dot-separated segments of alphanumeric characters, underscores, or hyphens.
+	pub fn is_str_translation_key(content: &str) -> bool {
+		content.contains('.') && content.split('.').all(|segment| {
+			!segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+		})
 	}
 
 	pub fn is_templatable(&self) -> bool {
@@ -66,6 +89,123 @@ impl TemplatableString {
 		Ok(result)
 	}
 
+	/// Finds the byte index of the [`EXPRESSION_CLOSE`](Self::EXPRESSION_CLOSE) matching an expression
+	/// whose body begins at `start`, skipping over nested expressions.
+	fn expression_end(content: &str, start: usize) -> Option<usize> {
+		let mut depth = 1;
+		let mut index = start;
+		while index < content.len() {
+			let rest = &content[index..];
+			if rest.starts_with(Self::EXPRESSION_OPEN) {
+				depth += 1;
+				index += Self::EXPRESSION_OPEN.len();
+			}
+			else if rest.starts_with(Self::EXPRESSION_CLOSE) {
+				depth -= 1;
+				if depth == 0 {
+					return Some(index);
+				}
+				index += Self::EXPRESSION_CLOSE.len();
+			}
+			else {
+				index += rest.chars().next().unwrap().len_utf8();
+			}
+		}
+		None
+	}
+
+	/// Splits an expression body around the first standalone `keyword` that is neither
+	/// inside a double-quoted string nor inside a nested expression.
+	fn split_keyword<'a>(body: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+		let mut depth = 0;
+		let mut quoted = false;
+		let mut escaped = false;
+		let mut previous = ' ';
+		for (index, c) in body.char_indices() {
+			let rest = &body[index..];
+			if escaped {
+				escaped = false;
+			}
+			else if c == '\\' {
+				escaped = true;
+			}
+			else if c == '"' {
+				quoted = !quoted;
+			}
+			else if !quoted && rest.starts_with(Self::EXPRESSION_OPEN) {
+				depth += 1;
+			}
+			else if !quoted && rest.starts_with(Self::EXPRESSION_CLOSE) {
+				depth -= 1;
+			}
+			else if !quoted && depth == 0 && previous.is_whitespace() && rest.starts_with(keyword) {
+				let after = &rest[keyword.len()..];
+				if after.chars().next().map(char::is_whitespace).unwrap_or(true) {
+					return Some((&body[..index], after));
+				}
+			}
+			previous = c;
+		}
+		None
+	}
+
+	/// Trims an expression branch and, if it is wrapped in double quotes, removes them and
+	/// resolves `\"` and `\\` escapes.
+	///
+	/// Quoting allows a branch to contain the `then` and `else` keywords or leading and trailing whitespace.
+	fn expression_branch(branch: &str) -> String {
+		let trimmed = branch.trim();
+		let unquoted = trimmed.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+		match unquoted {
+			None => trimmed.to_owned(),
+			Some(inner) => {
+				let mut result = String::with_capacity(inner.len());
+				let mut chars = inner.chars();
+				while let Some(c) = chars.next() {
+					match c {
+						'\\' => result.push(chars.next().unwrap_or('\\')),
+						_ => result.push(c)
+					}
+				}
+				result
+			}
+		}
+	}
+
+	/// Evaluates a single expression body.
+	///
+	/// The only supported expression is the conditional `if COND then A else B`, where `COND` follows the
+	/// [`Condition`] grammar and the `else` branch is optional. The selected branch may itself contain expressions.
+	fn evaluate_expression(body: &str, context: &TextContext) -> Result<String> {
+		let conditional = body.trim_start().strip_prefix("if")
+			.filter(|rest| rest.starts_with(char::is_whitespace))
+			.ok_or(anyhow!("Unknown template expression '{}'", body.trim()))?;
+		let (condition, branches) = Self::split_keyword(conditional, "then")
+			.ok_or(anyhow!("Template conditional '{}' lacks a `then` branch", body.trim()))?;
+		let (then, otherwise) = Self::split_keyword(branches, "else").unwrap_or((branches, ""));
+		let result = if Condition::parse(condition)?.evaluate(context)? { then } else { otherwise };
+		Self::expressions(&Self::expression_branch(result), context)
+	}
+
+	/// Replaces every top-level `{{ ... }}` expression in some content with its evaluated result.
+	fn expressions(content: &str, context: &TextContext) -> Result<String> {
+		if !content.contains(Self::EXPRESSION_OPEN) {
+			return Ok(content.to_owned());
+		}
+		let mut result = String::with_capacity(content.len());
+		let mut rest = content;
+		while let Some(start) = rest.find(Self::EXPRESSION_OPEN) {
+			let body_start = start + Self::EXPRESSION_OPEN.len();
+			let end = Self::expression_end(rest, body_start)
+				.ok_or(anyhow!("Unclosed '{}' in '{content}'", Self::EXPRESSION_OPEN))?;
+			result.push_str(&rest[..start]);
+			result.push_str(&Self::evaluate_expression(&rest[body_start..end], context)?);
+			rest = &rest[(end + Self::EXPRESSION_CLOSE.len())..];
+		}
+		result.push_str(rest);
+		Ok(result)
+	}
+
 	/// Attempts to retrieve a content string from the passed-in lang file.
 	/// 
 	/// Prior to formatting, the text content may represent a language key such as `some.key.here`.
@@ -77,20 +217,127 @@ impl TemplatableString {
 			.unwrap_or(&self.content)
 	}
 
-	fn fill_variable<'a>(var: &str, variables: &'a Variables, context: &TextContext) -> Option<String> {
-		context.global_variable(var).or(variables.get(var).cloned())
+	/// Creates a string whose content is displayed as-is, escaping every delimiter character.
+	pub fn literal(content: &str) -> Self {
+		let mut result = String::with_capacity(content.len());
+		for c in content.chars() {
+			if Self::ESCAPABLE.contains(&c) {
+				result.push(Self::ESCAPE);
+			}
+			result.push(c);
+		}
+		result.into()
+	}
+
+	/// Replaces each escaped delimiter with a placeholder character that no templating stage reacts to.
+	/// 
+	/// An escape character followed by anything else is kept as-is.
+	fn escape(content: &str) -> String {
+		let mut result = String::with_capacity(content.len());
+		let mut chars = content.chars().peekable();
+		while let Some(c) = chars.next() {
+			let escaped = (c == Self::ESCAPE)
+				.then(|| chars.peek().and_then(|next| Self::ESCAPABLE.iter().position(|e| e == next)))
+				.flatten();
+			match escaped {
+				Some(index) => {
+					chars.next();
+					result.push(char::from_u32(Self::ESCAPED_BASE + index as u32).unwrap());
+				},
+				None => result.push(c)
+			}
+		}
+		result
+	}
+
+	/// Restores the characters replaced by [`TemplatableString::escape`].
+	fn unescape(content: &str) -> String {
+		content.chars()
+			.map(|c| {
+				(c as u32).checked_sub(Self::ESCAPED_BASE)
+					.and_then(|index| Self::ESCAPABLE.get(index as usize))
+					.copied()
+					.unwrap_or(c)
+			})
+			.collect()
+	}
+
+	/// Returns the `(...)` script components in some content that can be checked before filling.
+	/// 
+	/// Escaped delimiters are skipped, as are components containing other templates, since their names aren't known until filled.
+	pub fn script_components(content: &str) -> Vec<String> {
+		Self::components(&Self::escape(content), '(', ')')
 	}
 
+	/// Returns the `<...>` variable components in some content that can be checked before filling.
+	/// 
+	/// Components within `{{ ... }}` expressions are skipped along with those skipped by [`TemplatableString::script_components`].
+	pub fn variable_components(content: &str) -> Vec<String> {
+		let escaped = Self::escape(content);
+		let mut outside = String::with_capacity(escaped.len());
+		let mut rest = escaped.as_str();
+		while let Some(start) = rest.find(Self::EXPRESSION_OPEN) {
+			outside.push_str(&rest[..start]);
+			match Self::expression_end(rest, start + Self::EXPRESSION_OPEN.len()) {
+				Some(end) => rest = &rest[(end + Self::EXPRESSION_CLOSE.len())..],
+				None => {
+					rest = "";
+					break;
+				}
+			}
+		}
+		outside.push_str(rest);
+		Self::components(&outside, '<', '>')
+	}
+
+	/// Collects the unescaped components between delimiters in escaped content that don't contain other templates.
+	fn components(escaped: &str, before: char, after: char) -> Vec<String> {
+		let mut result = Vec::new();
+		let mut last_opener: Option<usize> = None;
+		for (index, c) in escaped.char_indices() {
+			if c == before {
+				last_opener = Some(index);
+			}
+			else if c == after {
+				if let Some(lb) = last_opener.take() {
+					let component = &escaped[(lb + 1)..index];
+					if !Self::is_str_templatable(component) {
+						result.push(Self::unescape(component));
+					}
+				}
+			}
+		}
+		result
+	}
+
+	/// Handles a script component that failed to evaluate within some content.
+	/// 
+	/// In debug mode, the error is logged in full and the component is substituted with
+	/// [`TemplatableString::SCRIPT_ERROR_VALUE`] so that the game can continue.
+	/// Otherwise, returns an error naming the script and content without the Lua traceback.
+	fn script_error(error: anyhow::Error, script: &str, content: &str, context: &TextContext) -> Result<Option<String>> {
+		if context.config.settings.debug {
+			log::error!("Error in script '{script}' of '{content}': {error:?}");
+			return Ok(Some(Self::SCRIPT_ERROR_VALUE.to_owned()));
+		}
+		Err(anyhow!("Script '{script}' failed in '{content}': {}", Scripts::error_message(&error)))
+	}
+
+	/// Fills this string's content.
+	/// 
+	/// Translation is applied first, followed by `{{ ... }}` expressions, `(...)` scripts, and `<...>` variables, in that order.
+	/// Delimiters preceded by [`TemplatableString::ESCAPE`] are printed literally, as is a doubled escape character.
 	pub fn fill(&self, context: &TextContext) -> Result<String> {
-		let content = self.lang_file_content(context.lang_file);
-		let scripted = Self::template(content, '(', ')', move |var| {
+		let content = Self::escape(self.lang_file_content(context.lang_file));
+		let expressed = Self::expressions(&content, context)?;
+		let scripted = Self::template(&expressed, '(', ')', |var| {
 			context.scripts.get(var, context)
+				.or_else(|error| Self::script_error(error, var, &expressed, context))
 		})?;
-		Self::template(&scripted, '<', '>', move |var| {
-			let filled = Self::fill_variable(var, &context.variables, &context)
-				.map(|s| s.clone());
-			Ok(filled)
-		})
+		let filled = Self::template(&scripted, '<', '>', move |var| {
+			context.variable(var)
+		})?;
+		Ok(Self::unescape(&filled))
 	}
 }
 
@@ -101,20 +348,21 @@ pub struct TemplatableValue<T> {
 	pub template: Option<TemplatableString>
 }
 
+impl<T> TemplatableValue<T> where T: DeserializeOwned + Clone + FromStr {
+	/// Parses a string as `T`, falling back to a template if it is templatable.
+	fn parse(string: String) -> std::result::Result<Self, serde_yaml::Error> {
+		match serde_yaml::from_str::<T>(&string) {
+			Ok(value) => Ok(Self::value(value)),
+			Err(_) if TemplatableString::is_str_templatable(&string) => Ok(Self::template(string)),
+			Err(err) => Err(err)
+		}
+	}
+}
+
 impl<'de, T> Deserialize<'de> for TemplatableValue<T> where T: DeserializeOwned + Clone + FromStr {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
 		let string = String::deserialize(deserializer)?;
-		let result = serde_yaml::from_str::<T>(&string).map_err(DeError::custom)
-			.map(|value| TemplatableValue {
-				value: Some(value),
-				template: None
-			});
-		if let Err(_) = &result {
-			if TemplatableString::is_str_templatable(&string) {
-				return Ok(TemplatableValue::template(string));
-			}
-		}
-		result
+		Self::parse(string).map_err(DeError::custom)
     }
 }
 
@@ -172,4 +420,146 @@ impl<T> TemplatableValue<T> {
 		}
 		unreachable!()
 	}
+}
+
+#[derive(Debug)]
+/// A [`TemplatableValue<bool>`] that can also be written as a [`Condition`] over the player's variables and notes.
+/// 
+/// A condition is written after [`TemplatableBool::CONDITION_PREFIX`], such as `if has_key and not door_open`,
+/// so that a misspelled `true` or `false` fails to load rather than checking a note or variable of that name.
+/// Conditions follow the usual precedence of `not`, then `and`, then `or`, and short-circuit from left to right.
+pub enum TemplatableBool {
+	Value(TemplatableValue<bool>),
+	Condition(Condition)
+}
+
+impl TemplatableBool {
+	/// The keyword that a condition starts with, matching the conditional `{{ if ... }}` expressions.
+	pub const CONDITION_PREFIX: &'static str = "if ";
+
+	pub fn value(value: bool) -> Self {
+		Self::Value(TemplatableValue::value(value))
+	}
+
+	/// The bool this is written as directly, if it isn't a template or condition.
+	pub fn as_value(&self) -> Option<bool> {
+		match self {
+			Self::Value(value) => value.value,
+			Self::Condition(_) => None
+		}
+	}
+
+	/// The condition this bool is written as, if any.
+	pub fn condition(&self) -> Option<&Condition> {
+		match self {
+			Self::Condition(condition) => Some(condition),
+			Self::Value(_) => None
+		}
+	}
+
+	/// Gets the bool from the templatable value or by evaluating the condition against the context.
+	pub fn get_value(&self, context: &TextContext) -> Result<bool> {
+		match self {
+			Self::Value(value) => value.get_value(context),
+			Self::Condition(condition) => condition.evaluate(context)
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for TemplatableBool {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+		let string = String::deserialize(deserializer)?;
+		match string.trim_start().strip_prefix(Self::CONDITION_PREFIX) {
+			Some(condition) => Condition::parse(condition).map(Self::Condition).map_err(DeError::custom),
+			None => TemplatableValue::parse(string).map(Self::Value).map_err(DeError::custom)
+		}
+    }
+}
+
+impl Serialize for TemplatableBool {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: serde::Serializer {
+		match self {
+			Self::Value(value) => value.serialize(serializer),
+			Self::Condition(condition) => format!("{}{condition}", Self::CONDITION_PREFIX).serialize(serializer)
+		}
+    }
+}
+
+impl Default for TemplatableBool {
+	fn default() -> Self {
+		Self::value(false)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeSet;
+
+	use crate::{core::{choice::{Notes, Variables}, manifest::Manifest, resources::Resources}, loading::base::Loader, text::context::TextContext};
+
+	use super::{TemplatableBool, TemplatableString};
+
+	/// Fills some content with a player whose `name` variable is `Bob`.
+	fn fill(content: &str) -> String {
+		let config = Manifest::minimal();
+		let loader = Loader::new(std::env::temp_dir().join("nage-test-empty"));
+		let resources = Resources::load(&loader, &config).unwrap();
+		let variables = Variables::from([("name".to_owned(), "Bob".to_owned())]);
+		let context = TextContext::new(&config, Notes::new(), variables, BTreeSet::new(), 0, "en_us", &resources);
+		TemplatableString::from(content.to_owned()).fill(&context).unwrap()
+	}
+
+	#[test]
+	fn escaped_expression() {
+		assert_eq!(fill(r"\{{ if name == 'Bob' then hi }}"), "{{ if name == 'Bob' then hi }}");
+		assert_eq!(fill(r"\{{ <name> }} and {{ if name == 'Bob' then hi }}"), "{{ Bob }} and hi");
+	}
+
+	#[test]
+	fn escaped_variable() {
+		assert_eq!(fill(r"\<name\> is <name>"), "<name> is Bob");
+		assert_eq!(fill(r"<name> \<name\>"), "Bob <name>");
+	}
+
+	#[test]
+	fn escaped_escape() {
+		assert_eq!(fill(r"a \\ b"), r"a \ b");
+		assert_eq!(fill(r"\\<name>"), r"\Bob");
+		assert_eq!(fill(r"\\\<name>"), r"\<name>");
+	}
+
+	#[test]
+	fn trailing_escape() {
+		assert_eq!(fill(r"ends with \"), r"ends with \");
+		assert_eq!(fill(r"<name>\"), r"Bob\");
+	}
+
+	#[test]
+	fn unknown_escape() {
+		assert_eq!(fill(r"\n and \a"), r"\n and \a");
+	}
+
+	#[test]
+	fn escape_in_expression_branch() {
+		assert_eq!(fill(r"{{ if name == 'Bob' then \<name\> else <name> }}"), "<name>");
+		assert_eq!(fill(r"{{ if name == 'Alice' then \<name\> else <name> \{{ }}"), "Bob {{");
+		assert_eq!(fill(r#"{{ if name == 'Bob' then " \(script\) " }}"#), " (script) ");
+	}
+
+	#[test]
+	fn literal_round_trips() {
+		let content = r"{{ <name> (script) \ }}";
+		assert_eq!(TemplatableString::literal(content).content, r"\{\{ \<name\> \(script\) \\ \}\}");
+		assert_eq!(fill(&TemplatableString::literal(content).content), content);
+	}
+
+	#[test]
+	fn templatable_bool() {
+		let parse = |content: &str| serde_yaml::from_str::<TemplatableBool>(content);
+		assert_eq!(parse("true").unwrap().as_value(), Some(true));
+		assert!(parse("ture").is_err());
+		assert!(parse("flase").is_err());
+		assert!(parse("if has_key and not door_open").unwrap().condition().is_some());
+		assert!(matches!(parse("<flag>").unwrap(), TemplatableBool::Value(value) if value.template.is_some()));
+	}
 }
\ No newline at end of file